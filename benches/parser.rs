@@ -0,0 +1,95 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use rust_irc::protocol::prefix::Prefix;
+use rust_irc::protocol::tags::{TagValue, Tags};
+use rust_irc::protocol::wire::{RawMsg, RawMsgRef};
+
+const LINES: &[&str] = &[
+    "@time=2020-03-30T15:04:05.123Z;msgid=a1b2c3 :dan!d@localhost PRIVMSG #chan :Hey what's up!",
+    ":irc.example.com CAP * LS * :multi-prefix extended-join sasl server-time message-tags",
+    ":irc.example.com 353 dan = #chan :@dan +alice bob carol dave eve mallory",
+    "PING :irc.example.com",
+];
+
+// the char-walking parser RawMsgRef replaced, kept as the baseline to
+// compare against
+fn from_string(x: String) -> RawMsg {
+    let mut i = x.chars().fuse().peekable();
+
+    let tags = if i.peek() == Some(&'@') {
+        let tags_string = i.by_ref().skip(1).take_while(|c| c != &' ').collect::<String>();
+        let mut tags = Tags::new();
+
+        for kv in tags_string.split(';') {
+            let kv: Vec<&str> = kv.split('=').collect();
+            let value = if kv.len() == 2 { TagValue::String(kv[1].to_string()) } else { TagValue::True };
+            tags.insert(kv[0].to_string(), value);
+        }
+
+        Some(tags)
+    } else {
+        None
+    };
+
+    let source = if i.peek() == Some(&':') {
+        let prefix = i.by_ref().skip(1).take_while(|c| c != &' ').collect::<String>();
+        let mut p = prefix.chars().fuse();
+
+        let nick = p.by_ref().take_while(|c| c != &'!').collect::<String>();
+        let (user, host) = if p.size_hint().0 > 0 {
+            let user = p.by_ref().take_while(|c| c != &'@').collect::<String>();
+            (Some(user), Some(p.collect::<String>()))
+        } else {
+            (None, None)
+        };
+
+        Some(Prefix{nick, user, host})
+    } else {
+        None
+    };
+
+    let command = i.by_ref().take_while(|c| c != &' ').collect::<String>();
+
+    let mut params: Vec<String> = i.by_ref()
+        .take_while(|c| c != &':')
+        .collect::<String>()
+        .trim()
+        .split(' ')
+        .map(|p| p.to_string())
+        .collect();
+
+    if i.size_hint().0 > 0 {
+        params.push(i.collect::<String>());
+    }
+
+    RawMsg{tags, source, command, params}
+}
+
+fn parse_benchmark(c: &mut Criterion) {
+    c.bench_function("baseline from_string", |b| {
+        b.iter(|| {
+            for line in LINES {
+                black_box(from_string(black_box(line.to_string())));
+            }
+        })
+    });
+
+    c.bench_function("RawMsgRef::parse", |b| {
+        b.iter(|| {
+            for line in LINES {
+                black_box(RawMsgRef::parse(black_box(line)).unwrap());
+            }
+        })
+    });
+
+    c.bench_function("RawMsgRef::parse + to_owned", |b| {
+        b.iter(|| {
+            for line in LINES {
+                black_box(RawMsgRef::parse(black_box(line)).unwrap().to_owned());
+            }
+        })
+    });
+}
+
+criterion_group!(benches, parse_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,409 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+
+use crate::protocol::command::Command;
+use crate::protocol::wire::RawMsg;
+
+/*
+ * IRCv3 capability negotiation, kept free of any IO so it can be driven by
+ * whatever owns the connection. It carries on tracking caps after CAP END,
+ * as servers add and remove them with cap-notify.
+ */
+
+/// What we ask for if nothing else is configured
+pub const DEFAULT_CAPS: &[&str] = &[
+    "account-notify",
+    "away-notify",
+    "cap-notify",
+    "extended-join",
+    "message-tags",
+    "multi-prefix",
+    "server-time",
+];
+
+// keep each CAP REQ well inside 512 bytes, whatever the server prefix is
+const MAX_REQ_LENGTH: usize = 400;
+
+/// A capability as advertised in CAP LS, e.g. `sasl=PLAIN,EXTERNAL`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capability {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl From<&str> for Capability {
+    fn from(x: &str) -> Capability {
+        let mut i = x.splitn(2, '=');
+        let name = i.next().unwrap_or("").to_string();
+        let value = i.next().map(|v| v.to_string());
+
+        Capability{name, value}
+    }
+}
+
+impl Capability {
+
+    /// The comma separated values, e.g. the mechanisms in `sasl=PLAIN,EXTERNAL`
+    pub fn values(&self) -> Vec<&str> {
+        match &self.value {
+            Some(value) => value.split(',').filter(|v| !v.is_empty()).collect(),
+            None => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CapState {
+    /// CAP LS hasn't been sent yet
+    Idle,
+    /// Waiting for the rest of a multi-line CAP LS
+    Listing,
+    /// Waiting for the server to ACK or NAK our requests
+    Requesting,
+    /// Nothing left to negotiate, CAP END can be sent when the caller is
+    /// done with anything else that has to happen first, such as SASL
+    Ready,
+    /// CAP END has been sent
+    Done,
+}
+
+#[derive(Debug, Clone)]
+pub struct CapNegotiator {
+    wanted: Vec<String>,
+    available: BTreeMap<String, Capability>,
+    enabled: BTreeSet<String>,
+    // requested but not yet ACKed or NAKed
+    requested: BTreeSet<String>,
+    pending_reqs: usize,
+    state: CapState,
+}
+
+impl CapNegotiator {
+
+    pub fn new(wanted: Vec<String>) -> CapNegotiator {
+        CapNegotiator {
+            wanted,
+            available: BTreeMap::new(),
+            enabled: BTreeSet::new(),
+            requested: BTreeSet::new(),
+            pending_reqs: 0,
+            state: CapState::Idle,
+        }
+    }
+
+    pub fn state(&self) -> CapState {
+        self.state
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.state == CapState::Ready
+    }
+
+    pub fn available(&self, name: &str) -> Option<&Capability> {
+        self.available.get(name)
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.enabled.contains(name)
+    }
+
+    pub fn enabled(&self) -> impl Iterator<Item = &str> {
+        self.enabled.iter().map(|c| c.as_ref())
+    }
+
+    /// The CAP LS that starts negotiation, sent before NICK and USER
+    pub fn start(&mut self) -> RawMsg {
+        self.state = CapState::Listing;
+        cap(&["LS", "302"])
+    }
+
+    /// The CAP END that finishes negotiation
+    pub fn end(&mut self) -> RawMsg {
+        self.state = CapState::Done;
+        cap(&["END"])
+    }
+
+    /// Feeds a message from the server in, giving back anything that needs
+    /// sending in reply. Anything other than CAP is ignored.
+    pub fn handle(&mut self, msg: &RawMsg) -> Vec<RawMsg> {
+        let (subcommand, params) = match Command::try_from(msg.clone()) {
+            Ok(Command::Cap { subcommand, params, .. }) => (subcommand.to_ascii_uppercase(), params),
+            _ => return vec![],
+        };
+
+        // a * before the list means there's more to come
+        let (more, list) = match params.as_slice() {
+            [star, list] if star == "*" => (true, list.as_ref()),
+            [list] => (false, list.as_ref()),
+            _ => (false, ""),
+        };
+
+        match subcommand.as_ref() {
+            "LS" if self.state == CapState::Listing => {
+                for cap in list.split(' ').filter(|c| !c.is_empty()) {
+                    let cap = Capability::from(cap);
+                    self.available.insert(cap.name.clone(), cap);
+                }
+
+                if more {
+                    return vec![];
+                }
+
+                let offered = self.available.keys().cloned().collect();
+                self.request_wanted(&offered)
+            }
+            "ACK" => {
+                for cap in list.split(' ').filter(|c| !c.is_empty()) {
+                    match cap.strip_prefix('-') {
+                        Some(cap) => self.enabled.remove(cap),
+                        None => self.enabled.insert(cap.to_string()),
+                    };
+
+                    self.requested.remove(cap.trim_start_matches('-'));
+                }
+
+                self.answered();
+                vec![]
+            }
+            "NAK" => {
+                for cap in list.split(' ').filter(|c| !c.is_empty()) {
+                    self.requested.remove(cap.trim_start_matches('-'));
+                }
+
+                self.answered();
+                vec![]
+            }
+            "NEW" => {
+                let mut offered = BTreeSet::new();
+
+                for cap in list.split(' ').filter(|c| !c.is_empty()) {
+                    let cap = Capability::from(cap);
+                    offered.insert(cap.name.clone());
+                    self.available.insert(cap.name.clone(), cap);
+                }
+
+                // only what's new, so anything NAKed before isn't asked for again
+                self.request_wanted(&offered)
+            }
+            "DEL" => {
+                for cap in list.split(' ').filter(|c| !c.is_empty()) {
+                    self.available.remove(cap);
+                    self.enabled.remove(cap);
+                }
+
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    // REQs for the wanted caps among `offered` that we don't have or
+    // haven't already asked for
+    fn request_wanted(&mut self, offered: &BTreeSet<String>) -> Vec<RawMsg> {
+        let wanted: Vec<&str> = self.wanted.iter()
+            .map(|c| c.as_ref())
+            .filter(|c| offered.contains(*c) && !self.enabled.contains(*c) && !self.requested.contains(*c))
+            .collect();
+
+        let reqs = requests(&wanted);
+        self.pending_reqs += reqs.len();
+        self.requested.extend(wanted.iter().map(|c| c.to_string()));
+
+        // after CAP END the answers just update what's enabled
+        if self.state != CapState::Done {
+            self.state = if self.pending_reqs > 0 { CapState::Requesting } else { CapState::Ready };
+        }
+
+        reqs
+    }
+
+    fn answered(&mut self) {
+        self.pending_reqs = self.pending_reqs.saturating_sub(1);
+
+        if self.pending_reqs == 0 && self.state == CapState::Requesting {
+            self.state = CapState::Ready;
+        }
+    }
+}
+
+fn cap(params: &[&str]) -> RawMsg {
+    RawMsg::new("CAP".to_string(), Some(params.iter().map(|p| p.to_string()).collect()))
+}
+
+// as few CAP REQs as the caps fit into. The server ACKs or NAKs each line
+// as a whole, so one cap it refuses sinks the rest of that line with it;
+// only asking for caps it offered keeps that from happening in practice.
+fn requests(caps: &[&str]) -> Vec<RawMsg> {
+    let mut reqs = vec![];
+    let mut line = String::new();
+
+    for cap in caps {
+        if !line.is_empty() && line.len() + 1 + cap.len() > MAX_REQ_LENGTH {
+            reqs.push(self::cap(&["REQ", &line]));
+            line.clear();
+        }
+
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(cap);
+    }
+
+    if !line.is_empty() {
+        reqs.push(self::cap(&["REQ", &line]));
+    }
+
+    reqs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(line: &str) -> RawMsg {
+        line.parse().unwrap()
+    }
+
+    fn wanted(caps: &[&str]) -> Vec<String> {
+        caps.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn multiline_ls_test() {
+        let mut negotiator = CapNegotiator::new(wanted(&["multi-prefix", "sasl", "server-time"]));
+
+        assert_eq!("CAP LS 302", negotiator.start().to_string());
+
+        let reqs = negotiator.handle(&msg(":irc.example.com CAP * LS * :multi-prefix extended-join"));
+        assert!(reqs.is_empty());
+        assert_eq!(CapState::Listing, negotiator.state());
+
+        let reqs = negotiator.handle(&msg(":irc.example.com CAP * LS :sasl=PLAIN,EXTERNAL account-notify"));
+        assert_eq!(vec!["CAP REQ :multi-prefix sasl".to_string()], reqs.iter().map(|r| r.to_string()).collect::<Vec<_>>());
+        assert_eq!(CapState::Requesting, negotiator.state());
+
+        assert_eq!(vec!["PLAIN", "EXTERNAL"], negotiator.available("sasl").unwrap().values());
+        assert!(negotiator.available("server-time").is_none());
+    }
+
+    #[test]
+    fn ack_test() {
+        let mut negotiator = CapNegotiator::new(wanted(&["multi-prefix", "sasl"]));
+        negotiator.start();
+        negotiator.handle(&msg(":irc.example.com CAP * LS :multi-prefix sasl"));
+
+        negotiator.handle(&msg(":irc.example.com CAP dan ACK :multi-prefix sasl"));
+
+        assert!(negotiator.is_ready());
+        assert!(negotiator.is_enabled("multi-prefix"));
+        assert!(negotiator.is_enabled("sasl"));
+        assert_eq!("CAP END", negotiator.end().to_string());
+        assert_eq!(CapState::Done, negotiator.state());
+    }
+
+    #[test]
+    fn nak_test() {
+        let mut negotiator = CapNegotiator::new(wanted(&["multi-prefix", "sasl"]));
+        negotiator.start();
+        negotiator.handle(&msg(":irc.example.com CAP * LS :multi-prefix sasl"));
+
+        negotiator.handle(&msg(":irc.example.com CAP dan NAK :multi-prefix sasl"));
+
+        assert!(negotiator.is_ready());
+        assert_eq!(0, negotiator.enabled().count());
+    }
+
+    #[test]
+    fn nothing_wanted_test() {
+        let mut negotiator = CapNegotiator::new(wanted(&["sasl"]));
+        negotiator.start();
+
+        let reqs = negotiator.handle(&msg(":irc.example.com CAP * LS :multi-prefix"));
+
+        assert!(reqs.is_empty());
+        assert!(negotiator.is_ready());
+    }
+
+    #[test]
+    fn split_requests_test() {
+        let caps: Vec<String> = (0..60).map(|i| format!("example.com/cap-{}", i)).collect();
+        let mut negotiator = CapNegotiator::new(caps.clone());
+        negotiator.start();
+
+        let reqs = negotiator.handle(&msg(&format!(":irc.example.com CAP * LS :{}", caps.join(" "))));
+        assert!(reqs.len() > 1);
+        assert!(reqs.iter().all(|r| r.to_string().len() < 512));
+
+        for req in &reqs {
+            let ack = format!(":irc.example.com CAP dan ACK :{}", req.params[1]);
+            assert!(!negotiator.is_ready());
+            negotiator.handle(&msg(&ack));
+        }
+
+        assert!(negotiator.is_ready());
+        assert_eq!(60, negotiator.enabled().count());
+    }
+
+    #[test]
+    fn cap_notify_test() {
+        let mut negotiator = CapNegotiator::new(wanted(&["server-time", "away-notify"]));
+        negotiator.start();
+        negotiator.handle(&msg(":irc.example.com CAP * LS :server-time cap-notify"));
+        negotiator.handle(&msg(":irc.example.com CAP dan ACK :server-time"));
+        negotiator.end();
+
+        negotiator.handle(&msg(":irc.example.com CAP dan DEL :server-time"));
+        assert!(!negotiator.is_enabled("server-time"));
+        assert!(negotiator.available("server-time").is_none());
+
+        let reqs = negotiator.handle(&msg(":irc.example.com CAP dan NEW :server-time away-notify batch"));
+        assert_eq!(vec!["CAP REQ :server-time away-notify".to_string()], reqs.iter().map(|r| r.to_string()).collect::<Vec<_>>());
+        assert_eq!(CapState::Done, negotiator.state());
+
+        negotiator.handle(&msg(":irc.example.com CAP dan ACK :server-time away-notify"));
+        assert!(negotiator.is_enabled("server-time"));
+        assert!(negotiator.is_enabled("away-notify"));
+        assert!(!negotiator.is_enabled("batch"));
+        assert_eq!(CapState::Done, negotiator.state());
+    }
+
+    #[test]
+    fn new_only_requests_whats_new_test() {
+        let mut negotiator = CapNegotiator::new(wanted(&["server-time", "away-notify", "batch"]));
+        negotiator.start();
+        negotiator.handle(&msg(":irc.example.com CAP * LS :server-time away-notify cap-notify"));
+        negotiator.handle(&msg(":irc.example.com CAP dan NAK :server-time away-notify"));
+        negotiator.end();
+
+        // server-time was refused, so only batch is asked for
+        let reqs = negotiator.handle(&msg(":irc.example.com CAP dan NEW :batch"));
+        assert_eq!(vec!["CAP REQ batch".to_string()], reqs.iter().map(|r| r.to_string()).collect::<Vec<_>>());
+
+        // nor is it asked for twice while the first REQ is unanswered
+        assert!(negotiator.handle(&msg(":irc.example.com CAP dan NEW :batch")).is_empty());
+
+        negotiator.handle(&msg(":irc.example.com CAP dan ACK :batch"));
+        assert!(negotiator.is_enabled("batch"));
+        assert!(negotiator.handle(&msg(":irc.example.com CAP dan NEW :batch")).is_empty());
+    }
+
+    #[test]
+    fn new_with_nothing_wanted_test() {
+        let mut negotiator = CapNegotiator::new(wanted(&["server-time"]));
+        negotiator.start();
+        negotiator.handle(&msg(":irc.example.com CAP * LS :cap-notify"));
+        negotiator.end();
+
+        assert!(negotiator.handle(&msg(":irc.example.com CAP dan NEW :batch")).is_empty());
+        assert_eq!(CapState::Done, negotiator.state());
+    }
+
+    #[test]
+    fn other_commands_ignored_test() {
+        let mut negotiator = CapNegotiator::new(wanted(&["sasl"]));
+        negotiator.start();
+
+        assert!(negotiator.handle(&msg(":irc.example.com NOTICE * :Looking up your hostname")).is_empty());
+        assert_eq!(CapState::Listing, negotiator.state());
+    }
+}
@@ -0,0 +1,178 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use unicode_normalization::UnicodeNormalization;
+
+/*
+ * Comparing nicks and channel names the way the server does, by its
+ * CASEMAPPING. Under rfc1459 `[]\~` are the uppercase of `{}|^`, so
+ * `foo[]` and `FOO{}` are the same nick.
+ */
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum CaseMapping {
+    /// Only A-Z and a-z
+    Ascii,
+    /// As ascii, with `[]\~` and `{}|^` too. The default, being what
+    /// servers used before CASEMAPPING was advertised.
+    #[default]
+    Rfc1459,
+    /// As rfc1459, without `~` and `^`
+    StrictRfc1459,
+    /// The PRECIS UsernameCaseMapped profile, for UTF-8 nicks
+    Rfc7613,
+}
+
+impl CaseMapping {
+
+    /// The mapping a CASEMAPPING value names, if it's one we know
+    pub fn from_name(name: &str) -> Option<CaseMapping> {
+        match name.to_ascii_lowercase().as_str() {
+            "ascii" => Some(CaseMapping::Ascii),
+            "rfc1459" => Some(CaseMapping::Rfc1459),
+            "strict-rfc1459" => Some(CaseMapping::StrictRfc1459),
+            "rfc7613" => Some(CaseMapping::Rfc7613),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CaseMapping::Ascii => "ascii",
+            CaseMapping::Rfc1459 => "rfc1459",
+            CaseMapping::StrictRfc1459 => "strict-rfc1459",
+            CaseMapping::Rfc7613 => "rfc7613",
+        }
+    }
+
+    /// `text` in lowercase, two names being the same if these are equal
+    pub fn fold(&self, text: &str) -> String {
+        match self {
+            CaseMapping::Ascii => text.to_ascii_lowercase(),
+            CaseMapping::Rfc1459 | CaseMapping::StrictRfc1459 => text
+                .chars()
+                .map(|c| match c {
+                    '[' => '{',
+                    ']' => '}',
+                    '\\' => '|',
+                    '~' if *self == CaseMapping::Rfc1459 => '^',
+                    c => c.to_ascii_lowercase(),
+                })
+                .collect(),
+            // fullwidth and halfwidth forms to their decompositions, then
+            // lowercase, then NFC
+            CaseMapping::Rfc7613 => text
+                .chars()
+                .flat_map(|c| match c {
+                    '\u{ff00}'..='\u{ffef}' => c.nfkd().collect::<Vec<_>>(),
+                    c => vec![c],
+                })
+                .collect::<String>()
+                .to_lowercase()
+                .nfc()
+                .collect(),
+        }
+    }
+
+    pub fn equal(&self, a: &str, b: &str) -> bool {
+        a == b || self.fold(a) == self.fold(b)
+    }
+}
+
+impl fmt::Display for CaseMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A nick or channel name that compares and hashes by its case mapping,
+/// for use as a map key, while keeping the name as it was given
+#[derive(Debug, Clone)]
+pub struct CaseMapped {
+    name: String,
+    folded: String,
+}
+
+impl CaseMapped {
+
+    pub fn new(name: &str, mapping: CaseMapping) -> CaseMapped {
+        CaseMapped {
+            name: name.to_string(),
+            folded: mapping.fold(name),
+        }
+    }
+
+    /// The name as it was given
+    pub fn as_str(&self) -> &str {
+        &self.name
+    }
+
+    pub fn folded(&self) -> &str {
+        &self.folded
+    }
+}
+
+impl PartialEq for CaseMapped {
+    fn eq(&self, other: &CaseMapped) -> bool {
+        self.folded == other.folded
+    }
+}
+
+impl Eq for CaseMapped {}
+
+impl Hash for CaseMapped {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.folded.hash(state);
+    }
+}
+
+impl fmt::Display for CaseMapped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn fold_test() {
+        assert_eq!("foo[]", CaseMapping::Ascii.fold("FOO[]"));
+        assert_eq!("foo{}|^", CaseMapping::Rfc1459.fold("FOO[]\\~"));
+        assert_eq!("foo{}|~", CaseMapping::StrictRfc1459.fold("FOO[]\\~"));
+        assert_eq!("foo[]", CaseMapping::Rfc7613.fold("FOO[]"));
+    }
+
+    #[test]
+    fn rfc7613_test() {
+        assert!(CaseMapping::Rfc7613.equal("ÉLODIE", "élodie"));
+        // fullwidth
+        assert!(CaseMapping::Rfc7613.equal("ＤＡＮ", "dan"));
+        // decomposed é, composed again
+        assert!(CaseMapping::Rfc7613.equal("e\u{301}lodie", "élodie"));
+        assert!(!CaseMapping::Rfc1459.equal("ÉLODIE", "élodie"));
+    }
+
+    #[test]
+    fn from_name_test() {
+        assert_eq!(Some(CaseMapping::StrictRfc1459), CaseMapping::from_name("strict-rfc1459"));
+        assert_eq!(Some(CaseMapping::Ascii), CaseMapping::from_name("ASCII"));
+        assert_eq!(None, CaseMapping::from_name("rfc3454"));
+        assert_eq!("rfc7613", CaseMapping::Rfc7613.to_string());
+    }
+
+    #[test]
+    fn key_test() {
+        let mut users = HashMap::new();
+        users.insert(CaseMapped::new("foo[]", CaseMapping::Rfc1459), 1);
+
+        assert_eq!(Some(&1), users.get(&CaseMapped::new("FOO{}", CaseMapping::Rfc1459)));
+        assert_eq!(None, users.get(&CaseMapped::new("foo[]_", CaseMapping::Rfc1459)));
+
+        // the name is kept as given
+        assert_eq!("foo[]", users.keys().next().unwrap().as_str());
+    }
+}
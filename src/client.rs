@@ -0,0 +1,1302 @@
+use std::convert::TryFrom;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::SinkExt;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::stream::{Stream, StreamExt};
+use tokio::sync::mpsc;
+use tokio::time;
+use tokio_util::codec::Framed;
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::cap::{self, CapNegotiator};
+use crate::casemap::CaseMapping;
+use crate::protocol::codec::{Decoding, Encoding, IrcCodec, IrcCodecError};
+use crate::isupport::ISupport;
+use crate::keepalive::{self, Keepalive, Timeout};
+use crate::mode::ModeBuilder;
+use crate::nick::{NickError, Nicks};
+use crate::protocol::command::Command;
+use crate::protocol::numeric::Response;
+use crate::protocol::split;
+use crate::protocol::wire::RawMsg;
+use crate::reconnect::ReconnectPolicy;
+use crate::sasl::{self, External, Mechanism, Plain, SaslAuthenticator, SaslError, SaslState, Scram};
+use crate::state::State;
+#[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+use crate::tls::{self, TlsError, TlsSettings};
+
+/*
+ * A connection to a server that registers, then passes messages between
+ * the server and the application. Once registered the IO is run by a task
+ * of its own, so messages can be sent from anywhere with a Sender, and
+ * that task reconnects when the connection's lost.
+ */
+
+// either a plain TcpStream or one wrapped in TLS
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+type Transport = Framed<Box<dyn Connection>, IrcCodec>;
+
+/// How long registration can take, from CAP LS to RPL_WELCOME
+pub const DEFAULT_REGISTRATION_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// The servers as `host:port`, tried in turn when connecting fails
+    pub servers: Vec<String>,
+    pub nick: String,
+    /// Tried in turn if `nick` is taken, before falling back to `nick` with
+    /// a `_` or digit on the end
+    pub alternate_nicks: Vec<String>,
+    /// Takes `nick` back once it's free, if we had to register with another
+    pub regain_nick: bool,
+    /// The username sent in USER, the nick if not set
+    pub username: Option<String>,
+    pub realname: String,
+    /// The caps to request, sasl being added when there's a way to log in
+    pub caps: Vec<String>,
+    /// Logs in with SCRAM-SHA-256 or PLAIN along with `sasl_password`, or
+    /// is the authzid for EXTERNAL when there's a client certificate
+    pub sasl_account: Option<String>,
+    pub sasl_password: Option<String>,
+    /// What to do with lines that aren't UTF-8
+    pub decoding: Decoding,
+    /// Channels and nicks whose messages are always transcoded with the
+    /// given encoding, whatever `decoding` is
+    pub target_encodings: Vec<(String, &'static Encoding)>,
+    /// How long the link can be quiet before we PING the server
+    pub ping_interval: Duration,
+    /// How long to wait for anything back from that PING before the
+    /// connection's given up as dead
+    pub ping_timeout: Duration,
+    /// How long the server has to welcome us before the connection's given
+    /// up on, SASL and all
+    pub registration_timeout: Duration,
+    /// Joined once registered
+    pub channels: Vec<String>,
+    /// User modes to set once registered, such as `+iw-x`
+    pub user_modes: Option<String>,
+    /// How to go about reconnecting when the connection's lost, or None to
+    /// end the `Client` stream instead
+    pub reconnect: Option<ReconnectPolicy>,
+    /// Connects over TLS when set
+    #[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+    pub tls: Option<TlsSettings>,
+}
+
+impl ClientConfig {
+
+    pub fn new(server: String, nick: String) -> ClientConfig {
+        ClientConfig {
+            servers: vec![server],
+            realname: nick.clone(),
+            nick,
+            alternate_nicks: vec![],
+            regain_nick: true,
+            username: None,
+            caps: cap::DEFAULT_CAPS.iter().map(|c| c.to_string()).collect(),
+            sasl_account: None,
+            sasl_password: None,
+            decoding: Decoding::Strict,
+            target_encodings: vec![],
+            ping_interval: keepalive::DEFAULT_INTERVAL,
+            ping_timeout: keepalive::DEFAULT_TIMEOUT,
+            registration_timeout: DEFAULT_REGISTRATION_TIMEOUT,
+            channels: vec![],
+            user_modes: None,
+            reconnect: None,
+            #[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+            tls: None,
+        }
+    }
+
+    #[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+    fn client_cert(&self) -> bool {
+        self.tls.as_ref().is_some_and(|tls| tls.cert_file.is_some())
+    }
+
+    #[cfg(not(any(feature = "tls-rustls", feature = "tls-native")))]
+    fn client_cert(&self) -> bool {
+        false
+    }
+
+    // in order of preference, the first the server offers being used. A
+    // password means SCRAM-SHA-256 if the server lists it and PLAIN if not,
+    // otherwise a client cert means EXTERNAL.
+    fn mechanisms(&self) -> Vec<Box<dyn Mechanism>> {
+        match (&self.sasl_account, &self.sasl_password) {
+            (Some(account), Some(password)) => vec![
+                Box::new(Scram::new(account.clone(), password.clone())),
+                Box::new(Plain::new(account.clone(), password.clone())),
+            ],
+            (account, None) if self.client_cert() => vec![Box::new(External::new(account.clone()))],
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    #[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+    Tls(TlsError),
+    /// A line couldn't be read or sent
+    Codec(IrcCodecError),
+    /// SASL failed, so registration was abandoned
+    Sasl(SaslError),
+    /// Every nick we'd take was refused, so registration was abandoned
+    Nick(NickError),
+    /// The server closed the connection before registration finished
+    Disconnected,
+    /// Nothing came back from the server after we PINGed it
+    PingTimeout,
+    /// The server didn't welcome us within `registration_timeout`
+    RegistrationTimeout,
+    /// The connection is gone, by the server closing it or otherwise
+    Closed,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "{}", e),
+            #[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+            ClientError::Tls(e) => write!(f, "{}", e),
+            ClientError::Codec(e) => write!(f, "{}", e),
+            ClientError::Sasl(e) => write!(f, "{}", e),
+            ClientError::Nick(e) => write!(f, "{}", e),
+            ClientError::Disconnected => write!(f, "disconnected during registration"),
+            ClientError::PingTimeout => write!(f, "ping timeout"),
+            ClientError::RegistrationTimeout => write!(f, "registration timed out"),
+            ClientError::Closed => write!(f, "connection closed"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> ClientError {
+        ClientError::Io(e)
+    }
+}
+
+#[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+impl From<TlsError> for ClientError {
+    fn from(e: TlsError) -> ClientError {
+        ClientError::Tls(e)
+    }
+}
+
+impl From<IrcCodecError> for ClientError {
+    fn from(e: IrcCodecError) -> ClientError {
+        ClientError::Codec(e)
+    }
+}
+
+impl From<SaslError> for ClientError {
+    fn from(e: SaslError) -> ClientError {
+        ClientError::Sasl(e)
+    }
+}
+
+impl From<NickError> for ClientError {
+    fn from(e: NickError) -> ClientError {
+        ClientError::Nick(e)
+    }
+}
+
+/// A cloneable handle for sending messages to the server from any task
+#[derive(Debug, Clone)]
+pub struct Sender {
+    tx: mpsc::UnboundedSender<RawMsg>,
+    // for how long our prefix is when splitting
+    state: Arc<RwLock<State>>,
+}
+
+impl Sender {
+
+    /// Queues `msg`. Anything wrong with it comes back as an error from the
+    /// `Client` stream, as it's only checked when it's encoded.
+    pub fn send(&self, msg: RawMsg) -> Result<(), ClientError> {
+        self.tx.send(msg).map_err(|_| ClientError::Closed)
+    }
+
+    /// Sends `text` to `target`, split over as many PRIVMSGs as it takes
+    pub fn privmsg(&self, target: &str, text: &str) -> Result<(), ClientError> {
+        self.split("PRIVMSG", target, text)
+    }
+
+    /// Sends `text` to `target`, split over as many NOTICEs as it takes
+    pub fn notice(&self, target: &str, text: &str) -> Result<(), ClientError> {
+        self.split("NOTICE", target, text)
+    }
+
+    /// Sends the changes in as many MODEs as the server's MODES limit needs
+    pub fn mode(&self, modes: &ModeBuilder) -> Result<(), ClientError> {
+        let (chanmodes, max_params) = self.state.read()
+            .map(|state| (state.isupport().chanmodes(), state.isupport().modes()))
+            .unwrap_or_else(|_| (ISupport::new().chanmodes(), Some(1)));
+
+        for msg in modes.build(&chanmodes, max_params) {
+            self.send(msg)?;
+        }
+
+        Ok(())
+    }
+
+    fn split(&self, command: &str, target: &str, text: &str) -> Result<(), ClientError> {
+        let prefix_length = self.state.read().map_or(split::DEFAULT_PREFIX_LENGTH, |state| state.prefix_length());
+
+        for msg in split::split_message(command, target, text, prefix_length) {
+            self.send(msg)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Something that happened on the connection
+#[derive(Debug)]
+pub enum Event {
+    /// Registered with `server`, at first or after reconnecting
+    Connected { server: String },
+    /// A message from the server
+    Message(RawMsg),
+    /// The connection to `server` was lost
+    Disconnected { server: String, error: ClientError },
+    /// Waiting `delay` before trying to connect again, `attempt` counting
+    /// from 1
+    Reconnecting { attempt: u32, delay: Duration },
+}
+
+type Incoming = mpsc::UnboundedSender<Result<Event, ClientError>>;
+
+/// A registered connection, which is a `Stream` of everything the server
+/// sends from RPL_WELCOME on, along with anything sent before it that
+/// registration didn't use, and of the connection coming and going. The
+/// stream ends once the connection is lost for good.
+pub struct Client {
+    incoming: mpsc::UnboundedReceiver<Result<Event, ClientError>>,
+    sender: Sender,
+    state: Arc<RwLock<State>>,
+}
+
+impl Client {
+
+    /// Connects and registers, only returning once a server has welcomed
+    /// us. The servers are tried in turn, for as long as `config.reconnect`
+    /// allows.
+    pub async fn connect(config: ClientConfig) -> Result<Client, ClientError> {
+        if config.servers.is_empty() {
+            return Err(ClientError::Io(io::Error::new(io::ErrorKind::InvalidInput, "no servers configured")));
+        }
+
+        let session = reconnect(&config, 0, 0, None).await?;
+
+        let (tx, outgoing) = mpsc::unbounded_channel();
+        let (incoming_tx, incoming) = mpsc::unbounded_channel();
+        let state = Arc::new(RwLock::new(State::new(config.nick.clone())));
+
+        tokio::spawn(supervise(config, session, outgoing, incoming_tx, state.clone()));
+
+        Ok(Client {
+            incoming,
+            sender: Sender{tx, state: state.clone()},
+            state,
+        })
+    }
+
+    pub fn sender(&self) -> Sender {
+        self.sender.clone()
+    }
+
+    /// The channels we're in and who's in them. Each message is fed in
+    /// before it comes out of the stream, so handlers see it applied. It's
+    /// started afresh on reconnecting.
+    pub fn state(&self) -> Arc<RwLock<State>> {
+        self.state.clone()
+    }
+
+    /// Our user modes, as the server last told us
+    pub fn user_modes(&self) -> BTreeSet<char> {
+        self.state.read().map(|state| state.user_modes().clone()).unwrap_or_default()
+    }
+
+    /// Whether the server has `cap` enabled for us, which changes with
+    /// cap-notify as well as on reconnecting
+    pub fn is_enabled(&self, cap: &str) -> bool {
+        self.state.read().is_ok_and(|state| state.is_enabled(cap))
+    }
+}
+
+impl Stream for Client {
+    type Item = Result<Event, ClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.incoming.poll_recv(cx)
+    }
+}
+
+// a registered connection to one of the servers
+struct Session {
+    // where the server is in the list, for carrying on the rotation
+    index: usize,
+    // the attempt that connected, 0 for the first try
+    attempt: u32,
+    server: String,
+    nicks: Nicks,
+    transport: Transport,
+    negotiator: CapNegotiator,
+    keepalive: Keepalive,
+    backlog: Vec<RawMsg>,
+}
+
+// errors that would only happen again
+fn retryable(e: &ClientError) -> bool {
+    match e {
+        ClientError::Sasl(_) | ClientError::Nick(_) => false,
+        #[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+        ClientError::Tls(TlsError::Io(_)) | ClientError::Tls(TlsError::Handshake(_)) => true,
+        #[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+        ClientError::Tls(_) => false,
+        _ => true,
+    }
+}
+
+// tries each server in turn from `index` until one registers. Attempts
+// after the first, or from `attempt` on if it's more than 0, wait out the
+// backoff, which `events` is told about.
+async fn reconnect(config: &ClientConfig, mut index: usize, mut attempt: u32, events: Option<&Incoming>) -> Result<Session, ClientError> {
+    loop {
+        if let Some(policy) = config.reconnect.as_ref().filter(|_| attempt > 0) {
+            let delay = policy.delay(attempt);
+            info!("reconnecting in {:?}", delay);
+
+            if let Some(events) = events {
+                let _ = events.send(Ok(Event::Reconnecting{attempt, delay}));
+            }
+
+            time::delay_for(delay).await;
+        }
+
+        let server = &config.servers[index % config.servers.len()];
+
+        let e = match open(config, index, attempt, server).instrument(info_span!("server", server = %server)).await {
+            Ok(session) => return Ok(session),
+            Err(e) => e,
+        };
+
+        warn!("couldn't connect to {}: {}", server, e);
+
+        attempt += 1;
+        index += 1;
+
+        match &config.reconnect {
+            Some(policy) if retryable(&e) && policy.allows(attempt) => {}
+            _ => return Err(e),
+        }
+    }
+}
+
+async fn open(config: &ClientConfig, index: usize, attempt: u32, server: &str) -> Result<Session, ClientError> {
+    let stream = TcpStream::connect(server).await?;
+    let stream = wrap(stream, config, server).await?;
+
+    info!("connected");
+
+    let mut codec = IrcCodec::new();
+    codec.set_decoding(config.decoding);
+    for (target, encoding) in &config.target_encodings {
+        codec.set_target_encoding(target, encoding);
+    }
+
+    let mut transport = Framed::new(stream, codec);
+    let mut keepalive = Keepalive::new(config.ping_interval, config.ping_timeout, Instant::now());
+    let mut nicks = Nicks::new(config.nick.clone(), config.alternate_nicks.clone(), config.regain_nick);
+    let registration = register(&mut transport, config, &mut nicks, &mut keepalive);
+    let (negotiator, backlog) = time::timeout(config.registration_timeout, registration)
+        .await
+        .map_err(|_| ClientError::RegistrationTimeout)??;
+
+    Ok(Session {
+        index,
+        attempt,
+        server: server.to_string(),
+        nicks,
+        transport,
+        negotiator,
+        keepalive,
+        backlog,
+    })
+}
+
+// runs a session at a time, reconnecting when one ends if the policy
+// allows, until it gives up or the Client is dropped
+async fn supervise(
+    config: ClientConfig,
+    mut session: Session,
+    mut outgoing: mpsc::UnboundedReceiver<RawMsg>,
+    incoming: Incoming,
+    state: Arc<RwLock<State>>,
+) {
+    // joined once registered, and rejoined after reconnecting
+    let mut channels = config.channels.clone();
+
+    loop {
+        if let Ok(mut state) = state.write() {
+            *state = State::new(session.nicks.current().to_string());
+
+            for msg in &session.backlog {
+                state.handle(msg);
+            }
+
+            state.set_caps(session.negotiator.enabled());
+
+            session.transport.codec_mut().set_casemapping(state.isupport().casemapping());
+        }
+
+        if let Some(modes) = config.user_modes.as_ref().filter(|m| !m.is_empty()) {
+            let mode = RawMsg::new("MODE".to_string(), Some(vec![session.nicks.current().to_string(), modes.clone()]));
+
+            if let Err(e) = session.transport.send(mode).await {
+                warn!("couldn't set user modes {}: {}", modes, e);
+            }
+        }
+
+        for channel in &channels {
+            let join = RawMsg::new("JOIN".to_string(), Some(vec![channel.clone()]));
+
+            if let Err(e) = session.transport.send(join).await {
+                warn!("couldn't join {}: {}", channel, e);
+            }
+        }
+
+        if incoming.send(Ok(Event::Connected{server: session.server.clone()})).is_err() {
+            return;
+        }
+
+        for msg in std::mem::take(&mut session.backlog) {
+            let _ = incoming.send(Ok(Event::Message(msg)));
+        }
+
+        let span = info_span!("server", server = %session.server);
+        let connected_at = Instant::now();
+
+        let error = match run(&mut session, &mut channels, &mut outgoing, &incoming, &state).instrument(span).await {
+            Some(error) => error,
+            None => return,
+        };
+
+        info!("disconnected from {}: {}", session.server, error);
+
+        let server = session.server.clone();
+        if incoming.send(Ok(Event::Disconnected{server, error})).is_err() {
+            return;
+        }
+
+        let policy = match &config.reconnect {
+            Some(policy) => policy,
+            None => return,
+        };
+
+        // carrying on the count if the server let us in only to drop us
+        let attempt = if connected_at.elapsed() >= policy.min_uptime { 1 } else { session.attempt + 1 };
+
+        if !policy.allows(attempt) {
+            error!("giving up after {} attempts", attempt - 1);
+            return;
+        }
+
+        // the same server first, in case it was only a blip
+        session = match reconnect(&config, session.index, attempt, Some(&incoming)).await {
+            Ok(session) => session,
+            Err(e) => {
+                error!("giving up: {}", e);
+                let _ = incoming.send(Err(e));
+                return;
+            }
+        };
+    }
+}
+
+#[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+async fn wrap(stream: TcpStream, config: &ClientConfig, server: &str) -> Result<Box<dyn Connection>, ClientError> {
+    match &config.tls {
+        Some(settings) => Ok(Box::new(tls::connect(stream, tls::server_name(server), settings).await?)),
+        None => Ok(Box::new(stream)),
+    }
+}
+
+#[cfg(not(any(feature = "tls-rustls", feature = "tls-native")))]
+async fn wrap(stream: TcpStream, _config: &ClientConfig, _server: &str) -> Result<Box<dyn Connection>, ClientError> {
+    Ok(Box::new(stream))
+}
+
+// CAP LS, USER and NICK, then caps and SASL until CAP END, and on until
+// RPL_WELCOME. Gives back the negotiator, which goes on tracking
+// cap-notify, and the messages the application should still see.
+async fn register(
+    transport: &mut Transport,
+    config: &ClientConfig,
+    nicks: &mut Nicks,
+    keepalive: &mut Keepalive,
+) -> Result<(CapNegotiator, Vec<RawMsg>), ClientError> {
+    let mut mechanisms = config.mechanisms();
+    let mut caps = config.caps.clone();
+
+    if !mechanisms.is_empty() && !caps.iter().any(|c| c == "sasl") {
+        caps.push("sasl".to_string());
+    }
+
+    // started once the caps are settled and we know what the server offers
+    let mut sasl: Option<SaslAuthenticator> = None;
+
+    let mut negotiator = CapNegotiator::new(caps);
+    transport.send(negotiator.start()).await?;
+
+    let user = RawMsg::new("USER".to_string(), Some(vec![
+        config.username.clone().unwrap_or_else(|| config.nick.clone()),
+        "0".to_string(),
+        "*".to_string(),
+        config.realname.clone(),
+    ]));
+    transport.send(user).await?;
+
+    transport.send(nicks.start()).await?;
+
+    let mut backlog = vec![];
+
+    while let Some(result) = transport.next().await {
+        let msg = match result {
+            Ok(msg) => msg,
+            Err(IrcCodecError::Io(e)) => return Err(ClientError::Io(e)),
+            Err(e) => {
+                warn!("error receiving line: {}", e);
+                continue;
+            }
+        };
+
+        for reply in negotiator.handle(&msg) {
+            transport.send(reply).await?;
+        }
+
+        if let Some(sasl) = sasl.as_mut() {
+            match sasl.handle(&msg) {
+                Ok(replies) => {
+                    for reply in replies {
+                        transport.send(reply).await?;
+                    }
+                }
+                Err(e) => {
+                    // carrying on unauthenticated could expose a cloak or nick
+                    transport.send(RawMsg::new("QUIT".to_string(), None)).await?;
+                    return Err(ClientError::Sasl(e));
+                }
+            }
+        }
+
+        if negotiator.is_ready() {
+            if sasl.is_none() && !mechanisms.is_empty() {
+                let names: Vec<&str> = mechanisms.iter().map(|m| m.name()).collect();
+                let names = names.join(", ");
+
+                match choose_mechanism(&negotiator, std::mem::take(&mut mechanisms)) {
+                    Some(mechanism) => {
+                        let mut authenticator = SaslAuthenticator::new(mechanism);
+                        transport.send(authenticator.start()).await?;
+                        sasl = Some(authenticator);
+                    }
+                    None => {
+                        let e = if negotiator.is_enabled("sasl") { SaslError::NoMechanism(names) } else { SaslError::Unavailable };
+
+                        // as with a failed login, rather than carrying on without one
+                        transport.send(RawMsg::new("QUIT".to_string(), None)).await?;
+                        return Err(ClientError::Sasl(e));
+                    }
+                }
+            }
+
+            let authenticating = sasl.as_ref().is_some_and(|s| s.state() == SaslState::Authenticating);
+
+            if !authenticating {
+                if let Some(sasl) = sasl.as_ref() {
+                    match sasl.account() {
+                        Some(account) => info!("logged in as {} with {}", account, sasl.mechanism()),
+                        None => info!("authenticated with {}", sasl.mechanism()),
+                    }
+                }
+
+                transport.send(negotiator.end()).await?;
+                info!("enabled caps: {}", negotiator.enabled().collect::<Vec<_>>().join(" "));
+            }
+        }
+
+        // a refused nick that's been answered with another
+        match nicks.handle(&msg) {
+            Ok(replies) if replies.is_empty() => {}
+            Ok(replies) => {
+                for reply in replies {
+                    transport.send(reply).await?;
+                }
+
+                continue;
+            }
+            Err(e) => {
+                transport.send(RawMsg::new("QUIT".to_string(), None)).await?;
+                return Err(ClientError::Nick(e));
+            }
+        }
+
+        // some servers hold back RPL_WELCOME until a PING cookie is answered
+        if let Some(pong) = keepalive.handle(&msg, Instant::now()) {
+            transport.send(pong).await?;
+            continue;
+        }
+
+        if let Ok(Command::Cap { .. }) | Ok(Command::Authenticate { .. }) = Command::try_from(msg.clone()) {
+            continue;
+        }
+
+        let welcome = msg.response() == Some(Response::RPL_WELCOME);
+        backlog.push(msg);
+
+        if welcome {
+            info!("registered");
+            return Ok((negotiator, backlog));
+        }
+    }
+
+    Err(ClientError::Disconnected)
+}
+
+// our most preferred mechanism out of those the server listed, if the sasl
+// cap was ACKed at all
+fn choose_mechanism(negotiator: &CapNegotiator, mechanisms: Vec<Box<dyn Mechanism>>) -> Option<Box<dyn Mechanism>> {
+    if !negotiator.is_enabled("sasl") {
+        return None;
+    }
+
+    let offered = negotiator.available("sasl").map(|cap| cap.values()).unwrap_or_default();
+    sasl::choose(mechanisms, &offered)
+}
+
+// keeps track of the channels we're in as they change, so they're what's
+// rejoined
+fn track(session: &Session, channels: &mut Vec<String>, msg: &RawMsg, casemapping: CaseMapping) {
+    let nick = session.nicks.current();
+    let ours = msg.source.as_ref().is_some_and(|s| casemapping.equal(&s.nick, nick));
+    let has = |channels: &[String], channel: &str| channels.iter().any(|c| casemapping.equal(c, channel));
+
+    match Command::try_from(msg.clone()) {
+        Ok(Command::Join { channels: joined, .. }) if ours => {
+            for channel in joined {
+                if !has(channels, &channel) {
+                    channels.push(channel);
+                }
+            }
+        }
+        Ok(Command::Part { channels: parted, .. }) if ours => {
+            channels.retain(|c| !has(&parted, c));
+        }
+        Ok(Command::Kick { channel, user, .. }) if casemapping.equal(&user, nick) => {
+            channels.retain(|c| !casemapping.equal(c, &channel));
+        }
+        _ => {}
+    }
+}
+
+// owns the session's transport until the connection ends, giving back
+// why, or None if the Client was dropped
+async fn run(
+    session: &mut Session,
+    channels: &mut Vec<String>,
+    outgoing: &mut mpsc::UnboundedReceiver<RawMsg>,
+    incoming: &Incoming,
+    state: &RwLock<State>,
+) -> Option<ClientError> {
+    loop {
+        tokio::select! {
+            result = session.transport.next() => match result {
+                Some(Ok(msg)) => {
+                    // keeps tracking caps after registration, for cap-notify
+                    let reqs = session.negotiator.handle(&msg);
+                    let mut monitor = vec![];
+
+                    if let Ok(mut state) = state.write() {
+                        // kicks or parts go by the nick we had when they were sent
+                        track(session, channels, &msg, state.isupport().casemapping());
+                        state.handle(&msg);
+                        state.set_caps(session.negotiator.enabled());
+                        session.transport.codec_mut().set_casemapping(state.isupport().casemapping());
+
+                        if msg.response() == Some(Response::RPL_ISUPPORT) {
+                            monitor = session.nicks.set_isupport(state.isupport());
+                        }
+                    }
+
+                    let pong = session.keepalive.handle(&msg, Instant::now());
+                    // only registration can run out of nicks
+                    let nicks = session.nicks.handle(&msg).unwrap_or_default();
+
+                    for reply in pong.into_iter().chain(reqs).chain(monitor).chain(nicks) {
+                        if let Err(e) = session.transport.send(reply).await {
+                            return Some(e.into());
+                        }
+                    }
+
+                    if incoming.send(Ok(Event::Message(msg))).is_err() {
+                        return None;
+                    }
+                }
+                Some(Err(IrcCodecError::Io(e))) => return Some(ClientError::Io(e)),
+                Some(Err(e)) => {
+                    if incoming.send(Err(e.into())).is_err() {
+                        return None;
+                    }
+                }
+                None => return Some(ClientError::Closed),
+            },
+            msg = outgoing.recv() => match msg {
+                Some(msg) => match session.transport.send(msg).await {
+                    Ok(()) => {}
+                    Err(IrcCodecError::Io(e)) => return Some(ClientError::Io(e)),
+                    Err(e) => {
+                        if incoming.send(Err(e.into())).is_err() {
+                            return None;
+                        }
+                    }
+                },
+                None => return None,
+            },
+            _ = time::delay_until(time::Instant::from_std(session.keepalive.deadline())) => {
+                match session.keepalive.expired(Instant::now()) {
+                    Some(Timeout::Ping(ping)) => {
+                        if let Err(e) = session.transport.send(ping).await {
+                            return Some(e.into());
+                        }
+                    }
+                    Some(Timeout::Dead) => return Some(ClientError::PingTimeout),
+                    None => {}
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::net::TcpListener;
+
+    fn msg(line: &str) -> RawMsg {
+        line.parse().unwrap()
+    }
+
+    async fn expect(transport: &mut Framed<TcpStream, IrcCodec>, line: &str) {
+        assert_eq!(msg(line), transport.next().await.unwrap().unwrap());
+    }
+
+    async fn next_message(client: &mut Client) -> RawMsg {
+        loop {
+            match client.next().await.unwrap().unwrap() {
+                Event::Message(msg) => return msg,
+                _ => continue,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn register_test() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut transport = Framed::new(stream, IrcCodec::new());
+
+            expect(&mut transport, "CAP LS 302").await;
+            expect(&mut transport, "USER dan 0 * :Dan").await;
+            expect(&mut transport, "NICK dan").await;
+
+            transport.send(msg(":irc.example.com CAP * LS :multi-prefix")).await.unwrap();
+            expect(&mut transport, "CAP REQ multi-prefix").await;
+
+            transport.send(msg(":irc.example.com CAP * ACK multi-prefix")).await.unwrap();
+            expect(&mut transport, "CAP END").await;
+
+            transport.send(msg("PING :cookie")).await.unwrap();
+            expect(&mut transport, "PONG cookie").await;
+
+            transport.send(msg(":irc.example.com NOTICE * :Looking up your hostname")).await.unwrap();
+            transport.send(msg(":irc.example.com 001 dan :Welcome")).await.unwrap();
+            transport.send(msg(":irc.example.com 002 dan :Your host is irc.example.com")).await.unwrap();
+
+            expect(&mut transport, "PRIVMSG #rust hello").await;
+        });
+
+        let mut config = ClientConfig::new(addr.to_string(), "dan".to_string());
+        config.realname = "Dan".to_string();
+        config.caps = vec!["multi-prefix".to_string()];
+
+        let mut client = Client::connect(config).await.unwrap();
+
+        assert!(matches!(client.next().await, Some(Ok(Event::Connected { .. }))));
+        assert_eq!("NOTICE", next_message(&mut client).await.command);
+        assert_eq!(Some(Response::RPL_WELCOME), next_message(&mut client).await.response());
+        assert_eq!(Some(Response::RPL_YOURHOST), next_message(&mut client).await.response());
+
+        // from another task, as an application would
+        let sender = client.sender();
+        tokio::spawn(async move { sender.privmsg("#rust", "hello").unwrap() }).await.unwrap();
+
+        server.await.unwrap();
+        assert!(matches!(client.next().await, Some(Ok(Event::Disconnected { error: ClientError::Closed, .. }))));
+        assert!(client.next().await.is_none());
+    }
+
+    // registration with no caps wanted
+    async fn welcome(transport: &mut Framed<TcpStream, IrcCodec>) {
+        expect(transport, "CAP LS 302").await;
+        expect(transport, "USER dan 0 * dan").await;
+        expect(transport, "NICK dan").await;
+
+        transport.send(msg(":irc.example.com CAP * LS :multi-prefix")).await.unwrap();
+        expect(transport, "CAP END").await;
+
+        transport.send(msg(":irc.example.com 001 dan :Welcome")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ping_timeout_test() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut transport = Framed::new(stream, IrcCodec::new());
+            welcome(&mut transport).await;
+
+            transport.send(msg("PING :abc def")).await.unwrap();
+            expect(&mut transport, "PONG :abc def").await;
+
+            // answered once, then ignored
+            expect(&mut transport, "PING rust-irc-1").await;
+            transport.send(msg(":irc.example.com PONG irc.example.com rust-irc-1")).await.unwrap();
+            expect(&mut transport, "PING rust-irc-2").await;
+
+            // held open until the client gives up
+            transport
+        });
+
+        let mut config = ClientConfig::new(addr.to_string(), "dan".to_string());
+        config.caps = vec![];
+        config.ping_interval = Duration::from_millis(50);
+        config.ping_timeout = Duration::from_millis(50);
+
+        let mut client = Client::connect(config).await.unwrap();
+        let _transport = server.await.unwrap();
+
+        let mut received = vec![];
+        while let Some(result) = client.next().await {
+            received.push(result);
+        }
+
+        assert!(matches!(received.last(), Some(Ok(Event::Disconnected { error: ClientError::PingTimeout, .. }))));
+    }
+
+    fn reconnecting(servers: Vec<String>) -> ClientConfig {
+        let mut config = ClientConfig::new(servers[0].clone(), "dan".to_string());
+        config.servers = servers;
+        config.caps = vec![];
+        config.reconnect = Some(ReconnectPolicy {
+            initial_delay: Duration::from_millis(10),
+            jitter: 0.0,
+            max_attempts: Some(2),
+            ..ReconnectPolicy::default()
+        });
+
+        config
+    }
+
+    #[tokio::test]
+    async fn reconnect_test() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut transport = Framed::new(stream, IrcCodec::new());
+            welcome(&mut transport).await;
+
+            expect(&mut transport, "JOIN #rust").await;
+            transport.send(msg(":dan!d@localhost JOIN #rust")).await.unwrap();
+            transport.send(msg(":dan!d@localhost JOIN #rust-offtopic")).await.unwrap();
+            transport.send(msg(":dan!d@localhost PART #rust-offtopic")).await.unwrap();
+            transport.send(msg(":alice!a@localhost JOIN #other")).await.unwrap();
+            drop(transport);
+
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut transport = Framed::new(stream, IrcCodec::new());
+            welcome(&mut transport).await;
+
+            expect(&mut transport, "JOIN #rust").await;
+        });
+
+        let mut config = reconnecting(vec![addr.to_string()]);
+        config.channels = vec!["#rust".to_string()];
+        config.caps = vec![];
+        // any session that registers starts the count over
+        config.reconnect.as_mut().unwrap().min_uptime = Duration::from_secs(0);
+
+        let mut client = Client::connect(config).await.unwrap();
+
+        let mut events = vec![];
+        while let Some(event) = client.next().await {
+            events.push(event);
+        }
+
+        server.await.unwrap();
+
+        let events: Vec<String> = events.iter()
+            .map(|e| match e {
+                Ok(Event::Message(msg)) => msg.command.clone(),
+                Ok(Event::Connected { .. }) => "connected".to_string(),
+                Ok(Event::Disconnected { .. }) => "disconnected".to_string(),
+                Ok(Event::Reconnecting { attempt, delay }) => format!("reconnecting {} {:?}", attempt, delay),
+                Err(_) => "gave up".to_string(),
+            })
+            .collect();
+
+        assert_eq!(
+            vec![
+                "connected", "001", "JOIN", "JOIN", "PART", "JOIN", "disconnected",
+                "reconnecting 1 10ms", "connected", "001", "disconnected",
+                "reconnecting 1 10ms", "reconnecting 2 20ms", "gave up",
+            ],
+            events
+        );
+    }
+
+    #[tokio::test]
+    async fn flapping_test() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // welcomed and dropped, over and over
+        let server = tokio::spawn(async move {
+            for _ in 0..3 {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut transport = Framed::new(stream, IrcCodec::new());
+                welcome(&mut transport).await;
+            }
+        });
+
+        let mut client = Client::connect(reconnecting(vec![addr.to_string()])).await.unwrap();
+
+        let mut events = vec![];
+        while let Some(event) = client.next().await {
+            events.push(event);
+        }
+
+        server.await.unwrap();
+
+        let events: Vec<String> = events.iter()
+            .filter_map(|e| match e {
+                Ok(Event::Disconnected { .. }) => Some("disconnected".to_string()),
+                Ok(Event::Reconnecting { attempt, .. }) => Some(format!("reconnecting {}", attempt)),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(vec!["disconnected", "reconnecting 1", "disconnected", "reconnecting 2", "disconnected"], events);
+    }
+
+    #[tokio::test]
+    async fn rotation_test() {
+        // nothing listening
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut transport = Framed::new(stream, IrcCodec::new());
+            welcome(&mut transport).await;
+
+            transport
+        });
+
+        let mut client = Client::connect(reconnecting(vec![closed.to_string(), addr.to_string()])).await.unwrap();
+        assert!(matches!(client.next().await, Some(Ok(Event::Connected { server })) if server == addr.to_string()));
+
+        // and giving up when there's no policy
+        let config = ClientConfig::new(closed.to_string(), "dan".to_string());
+        assert!(matches!(Client::connect(config).await, Err(ClientError::Io(_))));
+    }
+
+    #[tokio::test]
+    async fn nick_in_use_test() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut transport = Framed::new(stream, IrcCodec::new());
+
+            expect(&mut transport, "CAP LS 302").await;
+            expect(&mut transport, "USER dan 0 * dan").await;
+            expect(&mut transport, "NICK dan").await;
+
+            transport.send(msg(":irc.example.com 433 * dan :Nickname is already in use")).await.unwrap();
+            expect(&mut transport, "NICK daniel").await;
+            transport.send(msg(":irc.example.com 433 * daniel :Nickname is already in use")).await.unwrap();
+            expect(&mut transport, "NICK dan_").await;
+
+            transport.send(msg(":irc.example.com CAP * LS :multi-prefix")).await.unwrap();
+            expect(&mut transport, "CAP END").await;
+            transport.send(msg(":irc.example.com 001 dan_ :Welcome")).await.unwrap();
+
+            // the holder leaves a channel we share
+            transport.send(msg(":dan!d@elsewhere QUIT :bye")).await.unwrap();
+            expect(&mut transport, "NICK dan").await;
+            transport.send(msg(":dan_!d@localhost NICK dan")).await.unwrap();
+
+            transport
+        });
+
+        let mut config = ClientConfig::new(addr.to_string(), "dan".to_string());
+        config.alternate_nicks = vec!["daniel".to_string()];
+        config.caps = vec![];
+
+        let mut client = Client::connect(config).await.unwrap();
+        let _transport = server.await.unwrap();
+
+        assert_eq!(Some(Response::RPL_WELCOME), next_message(&mut client).await.response());
+        assert_eq!("QUIT", next_message(&mut client).await.command);
+        assert_eq!("NICK", next_message(&mut client).await.command);
+    }
+
+    #[tokio::test]
+    async fn state_test() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut transport = Framed::new(stream, IrcCodec::new());
+            welcome(&mut transport).await;
+
+            expect(&mut transport, "JOIN #rust").await;
+            transport.send(msg(":dan!d@localhost JOIN #rust")).await.unwrap();
+            transport.send(msg(":irc.example.com 353 dan = #rust :dan @alice")).await.unwrap();
+            transport.send(msg(":irc.example.com 366 dan #rust :End of /NAMES list")).await.unwrap();
+
+            transport
+        });
+
+        let mut config = ClientConfig::new(addr.to_string(), "dan".to_string());
+        config.channels = vec!["#rust".to_string()];
+        config.caps = vec![];
+
+        let mut client = Client::connect(config).await.unwrap();
+        let _transport = server.await.unwrap();
+
+        while next_message(&mut client).await.response() != Some(Response::RPL_ENDOFNAMES) {}
+
+        let state = client.state();
+        let state = state.read().unwrap();
+        assert_eq!("dan", state.nick());
+        assert!(state.channel("#rust").unwrap().member("alice").unwrap().is_op());
+    }
+
+    #[tokio::test]
+    async fn registration_timeout_test() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // reads what we send and never answers
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut transport = Framed::new(stream, IrcCodec::new());
+
+            expect(&mut transport, "CAP LS 302").await;
+            expect(&mut transport, "USER dan 0 * :dan").await;
+            expect(&mut transport, "NICK dan").await;
+
+            transport
+        });
+
+        let mut config = ClientConfig::new(addr.to_string(), "dan".to_string());
+        config.caps = vec![];
+        config.registration_timeout = Duration::from_millis(100);
+
+        let result = Client::connect(config).await;
+        assert!(matches!(result, Err(ClientError::RegistrationTimeout)));
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sasl_unavailable_test() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut transport = Framed::new(stream, IrcCodec::new());
+
+            expect(&mut transport, "CAP LS 302").await;
+            expect(&mut transport, "USER dan 0 * :dan").await;
+            expect(&mut transport, "NICK dan").await;
+
+            transport.send(msg(":irc.example.com CAP * LS :sasl=EXTERNAL")).await.unwrap();
+            expect(&mut transport, "CAP REQ sasl").await;
+            transport.send(msg(":irc.example.com CAP * NAK sasl")).await.unwrap();
+
+            expect(&mut transport, "QUIT").await;
+        });
+
+        let mut config = ClientConfig::new(addr.to_string(), "dan".to_string());
+        config.caps = vec![];
+        config.sasl_account = Some("dan".to_string());
+        config.sasl_password = Some("hunter2".to_string());
+
+        let result = Client::connect(config).await;
+        assert!(matches!(result, Err(ClientError::Sasl(SaslError::Unavailable))));
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn cap_del_test() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut transport = Framed::new(stream, IrcCodec::new());
+
+            expect(&mut transport, "CAP LS 302").await;
+            expect(&mut transport, "USER dan 0 * :dan").await;
+            expect(&mut transport, "NICK dan").await;
+
+            transport.send(msg(":irc.example.com CAP * LS :cap-notify server-time")).await.unwrap();
+            expect(&mut transport, "CAP REQ :cap-notify server-time").await;
+            transport.send(msg(":irc.example.com CAP * ACK :cap-notify server-time")).await.unwrap();
+            expect(&mut transport, "CAP END").await;
+
+            transport.send(msg(":irc.example.com 001 dan :Welcome")).await.unwrap();
+
+            transport
+        });
+
+        let mut config = ClientConfig::new(addr.to_string(), "dan".to_string());
+        config.caps = vec!["cap-notify".to_string(), "server-time".to_string()];
+
+        let mut client = Client::connect(config).await.unwrap();
+        let mut transport = server.await.unwrap();
+
+        while next_message(&mut client).await.response() != Some(Response::RPL_WELCOME) {}
+        assert!(client.is_enabled("server-time"));
+
+        transport.send(msg(":irc.example.com CAP dan DEL :server-time")).await.unwrap();
+        while next_message(&mut client).await.command != "CAP" {}
+
+        assert!(!client.is_enabled("server-time"));
+        assert!(client.is_enabled("cap-notify"));
+    }
+
+    #[tokio::test]
+    async fn user_modes_test() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut transport = Framed::new(stream, IrcCodec::new());
+            welcome(&mut transport).await;
+
+            expect(&mut transport, "MODE dan +iw").await;
+            transport.send(msg(":dan MODE dan :+iw")).await.unwrap();
+
+            transport
+        });
+
+        let mut config = ClientConfig::new(addr.to_string(), "dan".to_string());
+        config.caps = vec![];
+        config.user_modes = Some("+iw".to_string());
+
+        let mut client = Client::connect(config).await.unwrap();
+        let _transport = server.await.unwrap();
+
+        while next_message(&mut client).await.command != "MODE" {}
+
+        assert_eq!(vec!['i', 'w'], client.user_modes().into_iter().collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn target_encodings_test() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut codec = IrcCodec::new();
+            codec.set_target_encoding("#latin", encoding_rs::WINDOWS_1252);
+
+            let mut transport = Framed::new(stream, codec);
+            welcome(&mut transport).await;
+
+            transport.send(msg(":bob!b@localhost PRIVMSG #latin :café")).await.unwrap();
+            expect(&mut transport, "PRIVMSG #latin :café €").await;
+        });
+
+        let mut config = ClientConfig::new(addr.to_string(), "dan".to_string());
+        config.caps = vec![];
+        config.target_encodings = vec![("#Latin".to_string(), encoding_rs::WINDOWS_1252)];
+
+        let mut client = Client::connect(config).await.unwrap();
+
+        loop {
+            let msg = next_message(&mut client).await;
+            if msg.command == "PRIVMSG" {
+                assert_eq!("café", msg.params[1]);
+                break;
+            }
+        }
+
+        client.sender().privmsg("#latin", "café €").unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn disconnected_test() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut transport = Framed::new(stream, IrcCodec::new());
+
+            expect(&mut transport, "CAP LS 302").await;
+            expect(&mut transport, "USER dan 0 * dan").await;
+            expect(&mut transport, "NICK dan").await;
+        });
+
+        let config = ClientConfig::new(addr.to_string(), "dan".to_string());
+        assert!(matches!(Client::connect(config).await, Err(ClientError::Disconnected)));
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::BTreeMap;
+
+use crate::casemap::CaseMapping;
+use crate::protocol::numeric::Response;
+use crate::protocol::wire::RawMsg;
+
+/*
+ * What the server says it supports in RPL_ISUPPORT, built up over however
+ * many 005 lines it sends. Anything not advertised falls back to what
+ * servers assumed before 005 existed.
+ */
+
+const DEFAULT_CHANTYPES: &str = "#&";
+const DEFAULT_PREFIX: &str = "(ov)@+";
+// not in any spec, but what nearly every server advertises
+const DEFAULT_CHANMODES: &str = "beI,k,l,imnpst";
+const DEFAULT_MODES: usize = 3;
+const DEFAULT_NICKLEN: usize = 30;
+const DEFAULT_USERLEN: usize = 10;
+const DEFAULT_HOSTLEN: usize = 63;
+
+/// The channel modes by the kind of param they take, from CHANMODES
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChanModes {
+    /// Lists, such as bans, with a param when adding or removing, and which
+    /// list the entries without one
+    pub a: String,
+    /// Settings with a param when adding or removing, such as a key
+    pub b: String,
+    /// Settings with a param only when adding, such as a limit
+    pub c: String,
+    /// Flags, never with a param
+    pub d: String,
+}
+
+impl From<&str> for ChanModes {
+    fn from(x: &str) -> ChanModes {
+        let mut i = x.split(',').map(|s| s.to_string());
+
+        ChanModes {
+            a: i.next().unwrap_or_default(),
+            b: i.next().unwrap_or_default(),
+            c: i.next().unwrap_or_default(),
+            d: i.next().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ISupport {
+    // the values escapes and all, None being a token with no value
+    tokens: BTreeMap<String, Option<String>>,
+}
+
+impl ISupport {
+
+    pub fn new() -> ISupport {
+        ISupport::default()
+    }
+
+    /// Feeds a message from the server in, anything but RPL_ISUPPORT being
+    /// ignored
+    pub fn handle(&mut self, msg: &RawMsg) {
+        if msg.response() != Some(Response::RPL_ISUPPORT) {
+            return;
+        }
+
+        // <client> <1-13 tokens> :are supported by this server
+        let tokens = msg.params.get(1..msg.params.len().saturating_sub(1)).unwrap_or(&[]);
+
+        for token in tokens {
+            self.token(token);
+        }
+    }
+
+    /// Applies a single token, such as `NICKLEN=30`, or `-NICKLEN` which
+    /// takes it away again
+    pub fn token(&mut self, token: &str) {
+        if let Some(name) = token.strip_prefix('-') {
+            self.tokens.remove(name);
+            return;
+        }
+
+        let mut i = token.splitn(2, '=');
+        let name = i.next().unwrap_or("");
+
+        if !name.is_empty() {
+            self.tokens.insert(name.to_string(), i.next().map(unescape));
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.tokens.contains_key(name)
+    }
+
+    /// A token's value, Some(None) if it's advertised without one and None
+    /// if it's not advertised at all
+    pub fn get(&self, name: &str) -> Option<Option<&str>> {
+        self.tokens.get(name).map(|v| v.as_deref())
+    }
+
+    // a token's value, if it has a non-empty one
+    fn value(&self, name: &str) -> Option<&str> {
+        self.get(name).flatten().filter(|v| !v.is_empty())
+    }
+
+    fn number(&self, name: &str) -> Option<usize> {
+        self.value(name).and_then(|v| v.parse().ok())
+    }
+
+    /// How nicks and channel names compare, rfc1459 if it's not one we know
+    pub fn casemapping(&self) -> CaseMapping {
+        self.value("CASEMAPPING").and_then(CaseMapping::from_name).unwrap_or_default()
+    }
+
+    /// The characters a channel name can start with
+    pub fn chantypes(&self) -> &str {
+        match self.get("CHANTYPES") {
+            // advertised empty, there are no channels
+            Some(chantypes) => chantypes.unwrap_or(""),
+            None => DEFAULT_CHANTYPES,
+        }
+    }
+
+    pub fn is_channel(&self, target: &str) -> bool {
+        target.starts_with(|c| self.chantypes().contains(c))
+    }
+
+    /// The membership modes and their symbols, such as `('o', '@')`,
+    /// highest ranked first
+    pub fn prefix(&self) -> Vec<(char, char)> {
+        let prefix = match self.get("PREFIX") {
+            Some(prefix) => prefix.unwrap_or(""),
+            None => DEFAULT_PREFIX,
+        };
+
+        // (modes)symbols
+        let (modes, symbols) = match prefix.strip_prefix('(').and_then(|p| p.split_once(')')) {
+            Some(split) => split,
+            None => return vec![],
+        };
+
+        modes.chars().zip(symbols.chars()).collect()
+    }
+
+    pub fn chanmodes(&self) -> ChanModes {
+        ChanModes::from(self.value("CHANMODES").unwrap_or(DEFAULT_CHANMODES))
+    }
+
+    /// How many modes with a param can go in one MODE, None being no limit
+    pub fn modes(&self) -> Option<usize> {
+        match self.get("MODES") {
+            Some(Some(modes)) if !modes.is_empty() => modes.parse().ok(),
+            Some(_) => None,
+            None => Some(DEFAULT_MODES),
+        }
+    }
+
+    pub fn nicklen(&self) -> usize {
+        self.number("NICKLEN").unwrap_or(DEFAULT_NICKLEN)
+    }
+
+    pub fn network(&self) -> Option<&str> {
+        self.value("NETWORK")
+    }
+
+    /// How many targets `command` can be sent to at once. None is no limit,
+    /// which is also what's assumed for commands TARGMAX doesn't list.
+    pub fn targmax(&self, command: &str) -> Option<usize> {
+        self.value("TARGMAX")?
+            .split(',')
+            .filter_map(|t| t.split_once(':'))
+            .find(|(name, _)| name.eq_ignore_ascii_case(command))
+            .and_then(|(_, max)| max.parse().ok())
+    }
+
+    /// The longest a `nick!user@host` prefix of ours could be, for
+    /// splitting messages before our own is known
+    pub fn prefix_length(&self) -> usize {
+        let userlen = self.number("USERLEN").unwrap_or(DEFAULT_USERLEN);
+        let hostlen = self.number("HOSTLEN").unwrap_or(DEFAULT_HOSTLEN);
+
+        self.nicklen() + 1 + userlen + 1 + hostlen
+    }
+}
+
+// values escape anything awkward as \xHH, a space being \x20
+fn unescape(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+
+    while let Some((&b, tail)) = rest.split_first() {
+        let escaped = tail.strip_prefix(b"x").and_then(|hex| hex.get(..2)).and_then(|hex| {
+            std::str::from_utf8(hex).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        });
+
+        match escaped {
+            Some(byte) if b == b'\\' => {
+                bytes.push(byte);
+                rest = &tail[3..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(line: &str) -> RawMsg {
+        line.parse().unwrap()
+    }
+
+    fn isupport(lines: &[&str]) -> ISupport {
+        let mut isupport = ISupport::new();
+
+        for line in lines {
+            isupport.handle(&msg(line));
+        }
+
+        isupport
+    }
+
+    #[test]
+    fn defaults_test() {
+        let isupport = ISupport::new();
+
+        assert_eq!(CaseMapping::Rfc1459, isupport.casemapping());
+        assert_eq!(vec![('o', '@'), ('v', '+')], isupport.prefix());
+        assert_eq!("k", isupport.chanmodes().b);
+        assert_eq!(Some(3), isupport.modes());
+        assert!(isupport.is_channel("&local") && !isupport.is_channel("dan"));
+        assert_eq!(crate::protocol::split::DEFAULT_PREFIX_LENGTH, isupport.prefix_length());
+    }
+
+    #[test]
+    fn tokens_test() {
+        let isupport = isupport(&[
+            ":irc.example.com 005 dan CASEMAPPING=ascii CHANTYPES=# PREFIX=(qaohv)~&@%+ :are supported by this server",
+            ":irc.example.com 005 dan CHANMODES=beI,k,l,imnpst,XYZ MODES NICKLEN=16 NETWORK=Example\\x20Net :are supported by this server",
+            ":irc.example.com 005 dan TARGMAX=PRIVMSG:4,NOTICE:4,JOIN: EXCEPTS :are supported by this server",
+        ]);
+
+        assert_eq!(CaseMapping::Ascii, isupport.casemapping());
+        assert!(!isupport.is_channel("&local"));
+        assert_eq!(('q', '~'), isupport.prefix()[0]);
+        assert_eq!(5, isupport.prefix().len());
+        assert_eq!(ChanModes::from("beI,k,l,imnpst"), isupport.chanmodes());
+        assert_eq!(None, isupport.modes());
+        assert_eq!(16, isupport.nicklen());
+        assert_eq!(Some("Example Net"), isupport.network());
+        assert_eq!(Some(4), isupport.targmax("privmsg"));
+        assert_eq!(None, isupport.targmax("JOIN"));
+        assert_eq!(None, isupport.targmax("KICK"));
+        assert_eq!(Some(None), isupport.get("EXCEPTS"));
+    }
+
+    #[test]
+    fn negation_test() {
+        let mut isupport = isupport(&[":irc.example.com 005 dan NICKLEN=16 PREFIX= CHANTYPES= :are supported by this server"]);
+        assert!(isupport.prefix().is_empty());
+        assert_eq!("", isupport.chantypes());
+
+        isupport.handle(&msg(":irc.example.com 005 dan -NICKLEN -PREFIX :are supported by this server"));
+        assert_eq!(30, isupport.nicklen());
+        assert_eq!(2, isupport.prefix().len());
+        assert!(!isupport.contains("NICKLEN"));
+    }
+
+    #[test]
+    fn unescape_test() {
+        assert_eq!("a b=c\\", unescape("a\\x20b\\x3Dc\\x5C"));
+        assert_eq!("\\x2", unescape("\\x2"));
+        assert_eq!("\\xzz", unescape("\\xzz"));
+        assert_eq!("café", unescape("caf\\xC3\\xA9"));
+    }
+}
@@ -0,0 +1,137 @@
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+use crate::protocol::command::Command;
+use crate::protocol::wire::RawMsg;
+
+/*
+ * PING/PONG keepalive, answering the server's PINGs and sending our own
+ * when the link goes quiet. Like the cap module this does no IO of its
+ * own, the caller sleeps until the deadline and asks what to do.
+ */
+
+/// How long the link can be quiet before we PING
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(120);
+
+/// How long after our PING we give up on hearing anything back
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Timeout {
+    /// Nothing's been heard for a while, this PING should be sent
+    Ping(RawMsg),
+    /// Nothing's been heard since our PING, the connection is dead
+    Dead,
+}
+
+#[derive(Debug, Clone)]
+pub struct Keepalive {
+    interval: Duration,
+    timeout: Duration,
+    last_received: Instant,
+    // when our unanswered PING went
+    pinged: Option<Instant>,
+    next_token: u64,
+}
+
+impl Keepalive {
+
+    pub fn new(interval: Duration, timeout: Duration, now: Instant) -> Keepalive {
+        Keepalive {
+            interval,
+            timeout,
+            last_received: now,
+            pinged: None,
+            next_token: 1,
+        }
+    }
+
+    /// When `expired` should next be called
+    pub fn deadline(&self) -> Instant {
+        match self.pinged {
+            Some(pinged) => pinged + self.timeout,
+            None => self.last_received + self.interval,
+        }
+    }
+
+    /// Feeds a message from the server in, giving back the PONG to send if
+    /// it was a PING. Anything at all from the server shows it's alive, so
+    /// it needn't be the PONG to our PING.
+    pub fn handle(&mut self, msg: &RawMsg, now: Instant) -> Option<RawMsg> {
+        self.last_received = now;
+        self.pinged = None;
+
+        match Command::try_from(msg.clone()) {
+            // the token has to come back exactly, or the server drops us
+            Ok(Command::Ping { token, .. }) => Some(RawMsg::new("PONG".to_string(), Some(vec![token]))),
+            _ => None,
+        }
+    }
+
+    /// What to do now the deadline has passed, if it has
+    pub fn expired(&mut self, now: Instant) -> Option<Timeout> {
+        if now < self.deadline() {
+            return None;
+        }
+
+        if self.pinged.is_some() {
+            return Some(Timeout::Dead);
+        }
+
+        let token = format!("rust-irc-{}", self.next_token);
+        self.next_token += 1;
+        self.pinged = Some(now);
+
+        Some(Timeout::Ping(RawMsg::new("PING".to_string(), Some(vec![token]))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(line: &str) -> RawMsg {
+        line.parse().unwrap()
+    }
+
+    fn keepalive(now: Instant) -> Keepalive {
+        Keepalive::new(Duration::from_secs(120), Duration::from_secs(60), now)
+    }
+
+    #[test]
+    fn pong_test() {
+        let now = Instant::now();
+        let mut keepalive = keepalive(now);
+
+        assert_eq!(Some(msg("PONG :abc def")), keepalive.handle(&msg("PING :abc def"), now));
+        assert_eq!(Some(msg("PONG 12345")), keepalive.handle(&msg(":irc.example.com PING 12345"), now));
+        assert_eq!(None, keepalive.handle(&msg(":irc.example.com NOTICE * :hi"), now));
+    }
+
+    #[test]
+    fn idle_test() {
+        let now = Instant::now();
+        let mut keepalive = keepalive(now);
+
+        assert_eq!(None, keepalive.expired(now + Duration::from_secs(119)));
+        assert_eq!(now + Duration::from_secs(120), keepalive.deadline());
+
+        let later = now + Duration::from_secs(120);
+        assert_eq!(Some(Timeout::Ping(msg("PING rust-irc-1"))), keepalive.expired(later));
+        assert_eq!(later + Duration::from_secs(60), keepalive.deadline());
+
+        // the server answering puts it back to waiting out the interval
+        keepalive.handle(&msg(":irc.example.com PONG irc.example.com :rust-irc-1"), later);
+        assert_eq!(later + Duration::from_secs(120), keepalive.deadline());
+    }
+
+    #[test]
+    fn dead_test() {
+        let now = Instant::now();
+        let mut keepalive = keepalive(now);
+
+        assert!(matches!(keepalive.expired(now + Duration::from_secs(120)), Some(Timeout::Ping(_))));
+        assert_eq!(None, keepalive.expired(now + Duration::from_secs(150)));
+        assert_eq!(Some(Timeout::Dead), keepalive.expired(now + Duration::from_secs(180)));
+    }
+}
@@ -0,0 +1,13 @@
+pub mod cap;
+pub mod casemap;
+pub mod client;
+pub mod isupport;
+pub mod keepalive;
+pub mod mode;
+pub mod nick;
+pub mod protocol;
+pub mod reconnect;
+pub mod sasl;
+pub mod state;
+#[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+pub mod tls;
@@ -1,16 +1,25 @@
-mod protocol;
+use rust_irc::client::{Client, ClientConfig, Event};
+use rust_irc::protocol::codec;
+use rust_irc::protocol::command::{Command, Message};
+use rust_irc::reconnect::ReconnectPolicy;
+#[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+use rust_irc::tls::TlsSettings;
+
+use std::convert::TryFrom;
+use std::time::Duration;
+#[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+use std::path::PathBuf;
 
-use protocol::wire;
-
-use tokio::net::TcpStream;
-use tokio_util::codec::Framed;
 use tokio::stream::StreamExt;
-use futures::{FutureExt, SinkExt};
 use config::Config;
+use tracing::{error, info, info_span, warn, Instrument};
 
 #[tokio::main]
 pub async fn main() {
 
+    // RUST_LOG=rust_irc::raw=trace shows every line sent and received
+    tracing_subscriber::fmt::init();
+
     let mut settings = Config::default();
     settings
         // Add in `./Settings.toml`
@@ -19,71 +28,166 @@ pub async fn main() {
         // Eg.. `APP_DEBUG=1 ./target/app` would set the `debug` key
         .merge(config::Environment::with_prefix("APP")).unwrap();
 
-    let stream = TcpStream::connect(settings.get_str("server").unwrap()).await.unwrap();
+    let nick = settings.get_str("nick").unwrap();
 
-    let mut transport = Framed::new(stream, protocol::codec::IrcCodec::new());
+    run(&settings)
+        .instrument(info_span!("client", nick = %nick))
+        .await;
+}
+
+fn strings(settings: &Config, key: &str) -> Option<Vec<String>> {
+    settings.get_array(key).ok().map(|values| values.into_iter().map(|v| v.into_str().unwrap()).collect())
+}
 
-    let cap = wire::RawMsg::new("CAP".to_string(), Some(vec!["LS".to_string(), "302".to_string()]));
-    transport.send(cap).await;
+fn client_config(settings: &Config) -> Option<ClientConfig> {
+    let mut config = ClientConfig::new(settings.get_str("server").unwrap(), settings.get_str("nick").unwrap());
+    config.realname = settings.get_str("name").unwrap();
 
-    let user = wire::RawMsg::new("USER".to_string(), Some(vec![
-        settings.get_str("nick").unwrap(),
-        "0".to_string(),
-        "*".to_string(),
-        settings.get_str("name").unwrap(),
-    ]));
+    if let Some(nicks) = strings(settings, "alternate_nicks") {
+        config.alternate_nicks = nicks;
+    }
 
-    transport.send(user).await;
+    config.regain_nick = settings.get_bool("regain_nick").unwrap_or(true);
 
-    let nick = wire::RawMsg::new(
-        "NICK".to_string(),
-        Some(vec![
-            settings.get_str("nick").unwrap()
-        ])
-    );
-    transport.send(nick).await;
+    // more to rotate through when reconnecting
+    if let Some(servers) = strings(settings, "fallback_servers") {
+        config.servers.extend(servers);
+    }
 
-    // TODO this should wait until after we get the CAP resp
-    let cap_end = wire::RawMsg::new("CAP".to_string(), Some(vec!["END".to_string()]));
+    if let Some(channels) = strings(settings, "channels") {
+        config.channels = channels;
+    }
 
-    transport.send(cap_end).await;
+    config.user_modes = settings.get_str("user_modes").ok();
 
-    while let Some(result) = transport.next().await {
-        match result {
-            Ok(msg) => {
-                match msg.command.as_ref() {
-                    "PING" => {
-                        let pong = wire::RawMsg::new(
-                            "PONG".to_string(), None
-                        );
-
-                        transport.send(pong).await;
-                    },
-                    "PRIVMSG" => {
-                    
-                        if !msg.source.is_none() {
+    if settings.get_bool("reconnect").unwrap_or(true) {
+        config.reconnect = Some(ReconnectPolicy {
+            max_attempts: settings.get_int("reconnect_attempts").ok().map(|n| n as u32),
+            ..ReconnectPolicy::default()
+        });
+    }
+
+    if let Some(caps) = strings(settings, "caps") {
+        config.caps = caps;
+    }
+
+    config.sasl_account = settings.get_str("sasl_account").ok();
+    config.sasl_password = settings.get_str("sasl_password").ok();
+
+    // e.g. "windows-1252", for networks still sending legacy encodings
+    if let Ok(label) = settings.get_str("fallback_encoding") {
+        let encoding = match codec::Encoding::for_label(label.as_bytes()) {
+            Some(encoding) => encoding,
+            None => {
+                error!("fallback_encoding {:?} isn't an encoding we know", label);
+                return None;
+            }
+        };
 
-                            let mut params = msg.params.clone();
-                            params[0] = msg.source.unwrap().nick;
+        config.decoding = codec::Decoding::Fallback(encoding);
+    }
 
-                            let echo = wire::RawMsg::new(
-                                "PRIVMSG".to_string(), 
-                                Some(params),
-                            );
+    // channel or nick to encoding label, for the odd one that isn't UTF-8
+    if let Ok(targets) = settings.get_table("target_encodings") {
+        for (target, label) in targets {
+            let label = label.into_str().unwrap();
+            let encoding = match codec::Encoding::for_label(label.as_bytes()) {
+                Some(encoding) => encoding,
+                None => {
+                    error!("target_encodings {:?} isn't an encoding we know", label);
+                    return None;
+                }
+            };
 
-                            transport.send(echo).await;
+            config.target_encodings.push((target, encoding));
+        }
+    }
 
+    // in seconds
+    if let Ok(interval) = settings.get_int("ping_interval") {
+        config.ping_interval = Duration::from_secs(interval as u64);
+    }
+
+    if let Ok(timeout) = settings.get_int("ping_timeout") {
+        config.ping_timeout = Duration::from_secs(timeout as u64);
+    }
+
+    if let Ok(timeout) = settings.get_int("registration_timeout") {
+        config.registration_timeout = Duration::from_secs(timeout as u64);
+    }
+
+    let tls = settings.get_bool("tls").unwrap_or(false);
+
+    if !tls && settings.get_str("tls_cert").is_ok() {
+        error!("tls_cert is set but tls isn't enabled");
+        return None;
+    }
+
+    #[cfg(any(feature = "tls-rustls", feature = "tls-native"))]
+    if tls {
+        config.tls = Some(TlsSettings {
+            ca_file: settings.get_str("tls_ca").ok().map(PathBuf::from),
+            ca_only: settings.get_bool("tls_ca_only").unwrap_or(false),
+            cert_file: settings.get_str("tls_cert").ok().map(PathBuf::from),
+            key_file: settings.get_str("tls_key").ok().map(PathBuf::from),
+            fingerprint: settings.get_str("tls_fingerprint").ok(),
+        });
+    }
+
+    #[cfg(not(any(feature = "tls-rustls", feature = "tls-native")))]
+    if tls {
+        error!("tls is set but this was built without the tls-rustls or tls-native feature");
+        return None;
+    }
+
+    Some(config)
+}
+
+async fn run(settings: &Config) {
+    let config = match client_config(settings) {
+        Some(config) => config,
+        None => return,
+    };
+
+    let mut client = match Client::connect(config).await {
+        Ok(client) => client,
+        Err(e) => {
+            error!("{}, giving up", e);
+            return;
+        }
+    };
+
+    let sender = client.sender();
+
+    while let Some(result) = client.next().await {
+        match result {
+            Ok(Event::Message(msg)) => {
+                let message = match Message::try_from(msg) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        warn!("malformed command: {}", e);
+                        continue;
+                    }
+                };
+
+                match message.command {
+                    Command::Privmsg { text, .. } => {
+
+                        if let Some(source) = message.source {
+                            sender.privmsg(&source.nick, &text).unwrap();
                         }
 
                     },
                     _ => continue
                 }
             }
+            Ok(Event::Disconnected { server, error }) => warn!("lost {}: {}", server, error),
+            Ok(_) => continue,
             Err(e) =>{
-                println!("Got error receiving line! {}", e);
+                error!("error receiving line: {}", e);
             }
         }
     }
 
-    println!("Quit!");
+    info!("quit");
 }
@@ -0,0 +1,336 @@
+use crate::isupport::ChanModes;
+use crate::protocol::codec::MAX_BODY_LENGTH;
+use crate::protocol::wire::{RawMsg, MAX_PARAMS};
+
+/*
+ * Channel MODE strings, such as `+ov-b alice bob *!*@spam`, into typed
+ * changes and back. Which modes take a param depends on the server's
+ * CHANMODES and PREFIX, so both are needed to know how the params line up.
+ * User modes, such as `+iw`, are parsed here too.
+ */
+
+/// The kind of param a mode takes, from CHANMODES or PREFIX
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModeKind {
+    /// A list, such as bans, with a mask when adding or removing. Without
+    /// one it's asking for the list.
+    List,
+    /// A setting with a param when adding or removing, such as a key
+    Param,
+    /// A setting with a param only when adding, such as a limit
+    SetParam,
+    /// A flag, never with a param. Modes the server didn't list are taken
+    /// to be flags too.
+    Flag,
+    /// A membership mode, such as op, with the nick it's for
+    Prefix,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModeChange {
+    pub adding: bool,
+    pub mode: char,
+    pub kind: ModeKind,
+    pub arg: Option<String>,
+}
+
+/// The kind of param `mode` takes on a server with these CHANMODES and
+/// PREFIX
+pub fn kind(mode: char, chanmodes: &ChanModes, prefix: &[(char, char)]) -> ModeKind {
+    if prefix.iter().any(|(letter, _)| *letter == mode) {
+        ModeKind::Prefix
+    } else if chanmodes.a.contains(mode) {
+        ModeKind::List
+    } else if chanmodes.b.contains(mode) {
+        ModeKind::Param
+    } else if chanmodes.c.contains(mode) {
+        ModeKind::SetParam
+    } else {
+        ModeKind::Flag
+    }
+}
+
+/// Splits the params of a channel MODE, after the channel, into changes,
+/// each taking its param if its kind has one. A param that's missing off
+/// the end leaves `arg` None rather than failing.
+pub fn parse(params: &[String], chanmodes: &ChanModes, prefix: &[(char, char)]) -> Vec<ModeChange> {
+    let (modes, mut args) = match params.split_first() {
+        Some((modes, args)) => (modes, args.iter()),
+        None => return vec![],
+    };
+
+    let mut adding = true;
+    let mut changes = vec![];
+
+    for mode in modes.chars() {
+        match mode {
+            '+' => adding = true,
+            '-' => adding = false,
+            _ => {
+                let kind = kind(mode, chanmodes, prefix);
+
+                let takes_arg = match kind {
+                    ModeKind::List | ModeKind::Param | ModeKind::Prefix => true,
+                    ModeKind::SetParam => adding,
+                    ModeKind::Flag => false,
+                };
+
+                let arg = if takes_arg { args.next().cloned() } else { None };
+
+                changes.push(ModeChange{adding, mode, kind, arg});
+            }
+        }
+    }
+
+    changes
+}
+
+/// Splits a user mode string, such as `+iw-x`, into changes. They're all
+/// taken to be flags, as the few user modes with a param, such as a
+/// snomask, aren't advertised anywhere.
+pub fn parse_user(modes: &str) -> Vec<ModeChange> {
+    let mut adding = true;
+    let mut changes = vec![];
+
+    for mode in modes.chars() {
+        match mode {
+            '+' => adding = true,
+            '-' => adding = false,
+            _ => changes.push(ModeChange{adding, mode, kind: ModeKind::Flag, arg: None}),
+        }
+    }
+
+    changes
+}
+
+type Change = (bool, char, Option<String>);
+
+/// Mode changes for a channel, to be sent in as few MODEs as the server's
+/// MODES limit allows
+#[derive(Debug, Clone)]
+pub struct ModeBuilder {
+    target: String,
+    changes: Vec<Change>,
+}
+
+impl ModeBuilder {
+
+    pub fn new(target: &str) -> ModeBuilder {
+        ModeBuilder {
+            target: target.to_string(),
+            changes: vec![],
+        }
+    }
+
+    pub fn add(&mut self, mode: char, arg: Option<&str>) -> &mut ModeBuilder {
+        self.changes.push((true, mode, arg.map(|a| a.to_string())));
+        self
+    }
+
+    pub fn remove(&mut self, mode: char, arg: Option<&str>) -> &mut ModeBuilder {
+        self.changes.push((false, mode, arg.map(|a| a.to_string())));
+        self
+    }
+
+    pub fn push(&mut self, change: ModeChange) -> &mut ModeBuilder {
+        self.changes.push((change.adding, change.mode, change.arg));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The MODEs to send, with no more than `max_params` params in each,
+    /// such as ISupport::modes gives. Flags don't count towards the limit.
+    /// Whatever the limit, each MODE also keeps to the 15 params and 512
+    /// bytes any line can have. Removing a mode that `chanmodes` says only
+    /// takes a param when adding, such as `-l`, drops any param given.
+    pub fn build(&self, chanmodes: &ChanModes, max_params: Option<usize>) -> Vec<RawMsg> {
+        // the target and the mode string take two of the line's params
+        let max_params = max_params.unwrap_or(usize::MAX).clamp(1, MAX_PARAMS - 2);
+
+        let changes: Vec<Change> = self.changes.iter()
+            .map(|(adding, mode, arg)| match kind(*mode, chanmodes, &[]) {
+                ModeKind::SetParam if !adding => (*adding, *mode, None),
+                _ => (*adding, *mode, arg.clone()),
+            })
+            .collect();
+
+        let mut batches: Vec<Vec<&Change>> = vec![];
+
+        for change in &changes {
+            let fits = batches.last().is_some_and(|batch| {
+                let params = batch.iter().filter(|(_, _, arg)| arg.is_some()).count();
+
+                let mut longer = batch.clone();
+                longer.push(change);
+
+                // with the CR LF on the end
+                (change.2.is_none() || params < max_params) && self.line(&longer).to_string().len() + 2 <= MAX_BODY_LENGTH
+            });
+
+            match batches.last_mut() {
+                Some(batch) if fits => batch.push(change),
+                _ => batches.push(vec![change]),
+            }
+        }
+
+        batches.iter().map(|batch| self.line(batch)).collect()
+    }
+
+    fn line(&self, batch: &[&Change]) -> RawMsg {
+        let modes = mode_string(batch.iter().map(|(adding, mode, _)| (*adding, *mode)));
+        let args = batch.iter().filter_map(|(_, _, arg)| arg.clone());
+
+        let params = vec![self.target.clone(), modes].into_iter().chain(args).collect();
+        RawMsg::new("MODE".to_string(), Some(params))
+    }
+}
+
+// such as +ov-b, from each change's sign and letter
+fn mode_string(changes: impl Iterator<Item = (bool, char)>) -> String {
+    let mut modes = String::new();
+    let mut sign = None;
+
+    for (adding, mode) in changes {
+        if sign != Some(adding) {
+            modes.push(if adding { '+' } else { '-' });
+            sign = Some(adding);
+        }
+
+        modes.push(mode);
+    }
+
+    modes
+}
+
+/// The changes as a mode string and its params, such as `+ov-b` and
+/// `alice bob *!*@spam`
+pub fn to_params(changes: &[ModeChange]) -> Vec<String> {
+    let modes = mode_string(changes.iter().map(|c| (c.adding, c.mode)));
+
+    std::iter::once(modes).chain(changes.iter().filter_map(|c| c.arg.clone())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chanmodes() -> ChanModes {
+        ChanModes::from("beI,k,l,imnpst")
+    }
+
+    const PREFIX: &[(char, char)] = &[('o', '@'), ('h', '%'), ('v', '+')];
+
+    fn params(line: &str) -> Vec<String> {
+        line.split(' ').map(|p| p.to_string()).collect()
+    }
+
+    fn change(adding: bool, mode: char, kind: ModeKind, arg: Option<&str>) -> ModeChange {
+        ModeChange{adding, mode, kind, arg: arg.map(|a| a.to_string())}
+    }
+
+    #[test]
+    fn parse_test() {
+        let changes = parse(&params("+ov-b alice bob *!*@spam"), &chanmodes(), PREFIX);
+
+        assert_eq!(vec![
+            change(true, 'o', ModeKind::Prefix, Some("alice")),
+            change(true, 'v', ModeKind::Prefix, Some("bob")),
+            change(false, 'b', ModeKind::List, Some("*!*@spam")),
+        ], changes);
+    }
+
+    #[test]
+    fn param_kinds_test() {
+        // a limit only has a param when it's set, a key either way
+        let changes = parse(&params("+lk-lk+m 10 secret secret"), &chanmodes(), PREFIX);
+
+        assert_eq!(vec![
+            change(true, 'l', ModeKind::SetParam, Some("10")),
+            change(true, 'k', ModeKind::Param, Some("secret")),
+            change(false, 'l', ModeKind::SetParam, None),
+            change(false, 'k', ModeKind::Param, Some("secret")),
+            change(true, 'm', ModeKind::Flag, None),
+        ], changes);
+
+        // asking for the ban list, and a mode the server didn't list
+        let changes = parse(&params("+bZ"), &chanmodes(), PREFIX);
+        assert_eq!(vec![change(true, 'b', ModeKind::List, None), change(true, 'Z', ModeKind::Flag, None)], changes);
+    }
+
+    #[test]
+    fn parse_user_test() {
+        let changes = parse_user("+iw-x");
+
+        assert_eq!(vec![
+            change(true, 'i', ModeKind::Flag, None),
+            change(true, 'w', ModeKind::Flag, None),
+            change(false, 'x', ModeKind::Flag, None),
+        ], changes);
+    }
+
+    #[test]
+    fn build_test() {
+        let mut builder = ModeBuilder::new("#rust");
+        builder.add('o', Some("alice")).add('o', Some("bob")).add('m', None).add('v', Some("carol")).remove('b', Some("*!*@spam"));
+
+        let lines: Vec<String> = builder.build(&chanmodes(), Some(3)).iter().map(|m| m.to_string()).collect();
+        assert_eq!(vec!["MODE #rust +oomv alice bob carol", "MODE #rust -b *!*@spam"], lines);
+
+        let lines: Vec<String> = builder.build(&chanmodes(), None).iter().map(|m| m.to_string()).collect();
+        assert_eq!(vec!["MODE #rust +oomv-b alice bob carol *!*@spam"], lines);
+
+        assert!(ModeBuilder::new("#rust").build(&chanmodes(), Some(3)).is_empty());
+    }
+
+    #[test]
+    fn build_set_param_test() {
+        let mut builder = ModeBuilder::new("#rust");
+        builder.remove('l', Some("10")).remove('k', Some("secret")).add('l', Some("20"));
+
+        let lines: Vec<String> = builder.build(&chanmodes(), None).iter().map(|m| m.to_string()).collect();
+        assert_eq!(vec!["MODE #rust -lk+l secret 20"], lines);
+    }
+
+    #[test]
+    fn build_limits_test() {
+        let mut builder = ModeBuilder::new("#rust");
+        for i in 0..20 {
+            builder.add('o', Some(&format!("nick{:02}", i)));
+        }
+
+        // no MODES limit still leaves the 15 params a line can have
+        let msgs = builder.build(&chanmodes(), None);
+        assert_eq!(vec![15, 9], msgs.iter().map(|m| m.params.len()).collect::<Vec<_>>());
+        assert!(msgs.iter().all(|m| m.validate().is_ok()));
+
+        // nor can a line go past 512 bytes
+        let mut builder = ModeBuilder::new("#rust");
+        for i in 0..12 {
+            builder.add('b', Some(&format!("*!*@{:02}.{}", i, "x".repeat(60))));
+        }
+
+        let msgs = builder.build(&chanmodes(), None);
+        assert!(msgs.len() > 1);
+        assert!(msgs.iter().all(|m| m.to_string().len() + 2 <= MAX_BODY_LENGTH));
+        assert_eq!(12, msgs.iter().map(|m| m.params.len() - 2).sum::<usize>());
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let line = params("+ov-bk+l alice bob *!*@spam secret 10");
+        let changes = parse(&line, &chanmodes(), PREFIX);
+
+        assert_eq!(line, to_params(&changes));
+
+        let mut builder = ModeBuilder::new("#rust");
+        for change in changes {
+            builder.push(change);
+        }
+
+        assert_eq!(1, builder.build(&chanmodes(), Some(5)).len());
+        assert_eq!(2, builder.build(&chanmodes(), Some(4)).len());
+    }
+}
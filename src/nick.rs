@@ -0,0 +1,308 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::casemap::CaseMapping;
+use crate::isupport::ISupport;
+use crate::protocol::command::Command;
+use crate::protocol::numeric::Response;
+use crate::protocol::wire::RawMsg;
+
+/*
+ * Finding a nick the server will take during registration, and getting
+ * the one we wanted back once whoever has it lets go. Like the cap module
+ * this does no IO of its own.
+ */
+
+// digits tried on the end of the nick once the alternates are used up
+const MAX_SUFFIX: usize = 9;
+
+/// Every nick we'd take was refused during registration
+#[derive(Debug, Clone, PartialEq)]
+pub struct NickError {
+    /// The last nick tried
+    pub nick: String,
+}
+
+impl fmt::Display for NickError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no nick available, {} being the last refused", self.nick)
+    }
+}
+
+impl std::error::Error for NickError {}
+
+#[derive(Debug, Clone)]
+pub struct Nicks {
+    wanted: String,
+    alternates: Vec<String>,
+    // which candidate we're on, the wanted nick being 0
+    tried: usize,
+    current: String,
+    registered: bool,
+    regain: bool,
+    // whether the wanted nick is on our MONITOR list
+    monitoring: bool,
+    casemapping: CaseMapping,
+}
+
+impl Nicks {
+
+    /// `alternates` are tried in order when `wanted` is taken, then `wanted`
+    /// with a `_` and then a digit on the end. With `regain` the wanted nick
+    /// is taken back once it's free, which the server tells us about if it
+    /// supports MONITOR, otherwise we only see it if we share a channel
+    /// with whoever has it.
+    pub fn new(wanted: String, alternates: Vec<String>, regain: bool) -> Nicks {
+        Nicks {
+            current: wanted.clone(),
+            wanted,
+            alternates,
+            tried: 0,
+            registered: false,
+            regain,
+            monitoring: false,
+            casemapping: CaseMapping::default(),
+        }
+    }
+
+    /// The nick we have, or are trying for if registration isn't done
+    pub fn current(&self) -> &str {
+        &self.current
+    }
+
+    pub fn wanted(&self) -> &str {
+        &self.wanted
+    }
+
+    pub fn has_wanted(&self) -> bool {
+        self.casemapping.equal(&self.current, &self.wanted)
+    }
+
+    /// The NICK sent to start registration
+    pub fn start(&self) -> RawMsg {
+        nick(&self.current)
+    }
+
+    fn candidate(&self, n: usize) -> Option<String> {
+        let fallback = n.checked_sub(self.alternates.len() + 1);
+
+        match fallback {
+            None if n == 0 => Some(self.wanted.clone()),
+            None => self.alternates.get(n - 1).cloned(),
+            Some(0) => Some(format!("{}_", self.wanted)),
+            Some(i) if i <= MAX_SUFFIX => Some(format!("{}{}", self.wanted, i)),
+            Some(_) => None,
+        }
+    }
+
+    /// Takes the CASEMAPPING and MONITOR support from what the server's
+    /// advertised so far, giving back a MONITOR for the wanted nick if it
+    /// can be watched for now
+    pub fn set_isupport(&mut self, isupport: &ISupport) -> Vec<RawMsg> {
+        self.casemapping = isupport.casemapping();
+
+        if isupport.contains("MONITOR") && self.regain && !self.has_wanted() && !self.monitoring {
+            self.monitoring = true;
+            return vec![monitor("+", &self.wanted)];
+        }
+
+        vec![]
+    }
+
+    fn is(&self, nick: &str, other: &str) -> bool {
+        self.casemapping.equal(nick, other)
+    }
+
+    fn regain(&self) -> Vec<RawMsg> {
+        if self.regain && self.registered && !self.has_wanted() {
+            vec![nick(&self.wanted)]
+        } else {
+            vec![]
+        }
+    }
+
+    /// Feeds a message from the server in, giving back anything to send. An
+    /// error means there's no nick left to try.
+    pub fn handle(&mut self, msg: &RawMsg) -> Result<Vec<RawMsg>, NickError> {
+        let source = msg.source.as_ref().map(|s| s.nick.as_str()).unwrap_or("");
+
+        let command = match Command::try_from(msg.clone()) {
+            Ok(command) => command,
+            Err(_) => return Ok(vec![]),
+        };
+
+        match command {
+            Command::Response { response, params } => self.response(response, &params),
+            Command::Nick { nick } if self.is(source, &self.current) => {
+                self.current = nick;
+
+                if self.has_wanted() && self.monitoring {
+                    self.monitoring = false;
+                    return Ok(vec![monitor("-", &self.wanted)]);
+                }
+
+                Ok(vec![])
+            }
+            // whoever had it has moved on
+            Command::Nick { nick } if self.is(source, &self.wanted) && !self.is(&nick, &self.wanted) => Ok(self.regain()),
+            Command::Quit { .. } if self.is(source, &self.wanted) => Ok(self.regain()),
+            _ => Ok(vec![]),
+        }
+    }
+
+    fn response(&mut self, response: Response, params: &[String]) -> Result<Vec<RawMsg>, NickError> {
+        match response {
+            Response::ERR_NICKNAMEINUSE
+            | Response::ERR_ERRONEUSNICKNAME
+            | Response::ERR_NICKCOLLISION
+            | Response::ERR_UNAVAILRESOURCE if !self.registered => {
+                self.tried += 1;
+
+                match self.candidate(self.tried) {
+                    Some(candidate) => {
+                        self.current = candidate;
+                        Ok(vec![nick(&self.current)])
+                    }
+                    None => Err(NickError{nick: self.current.clone()}),
+                }
+            }
+            Response::RPL_WELCOME => {
+                self.registered = true;
+
+                // the server has the final say, it may have truncated it
+                if let Some(nick) = params.first() {
+                    self.current = nick.clone();
+                }
+
+                Ok(vec![])
+            }
+            // <client> :<target>{,<target>}
+            Response::RPL_MONOFFLINE => {
+                let offline = params.last().map(|targets| targets.split(',').any(|t| self.is(t, &self.wanted)));
+
+                if offline == Some(true) {
+                    return Ok(self.regain());
+                }
+
+                Ok(vec![])
+            }
+            _ => Ok(vec![]),
+        }
+    }
+}
+
+fn nick(nick: &str) -> RawMsg {
+    RawMsg::new("NICK".to_string(), Some(vec![nick.to_string()]))
+}
+
+fn monitor(op: &str, nick: &str) -> RawMsg {
+    RawMsg::new("MONITOR".to_string(), Some(vec![op.to_string(), nick.to_string()]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(line: &str) -> RawMsg {
+        line.parse().unwrap()
+    }
+
+    fn lines(msgs: Vec<RawMsg>) -> Vec<String> {
+        msgs.iter().map(|m| m.to_string()).collect()
+    }
+
+    #[test]
+    fn alternates_test() {
+        let mut nicks = Nicks::new("dan".to_string(), vec!["daniel".to_string()], false);
+        assert_eq!("NICK dan", nicks.start().to_string());
+
+        let mut tried = vec![];
+        loop {
+            match nicks.handle(&msg(":irc.example.com 433 * x :Nickname is already in use")) {
+                Ok(replies) => tried.extend(lines(replies)),
+                Err(e) => {
+                    assert_eq!("dan9", e.nick);
+                    break;
+                }
+            }
+        }
+
+        assert_eq!(
+            vec!["NICK daniel", "NICK dan_", "NICK dan1", "NICK dan2", "NICK dan3", "NICK dan4", "NICK dan5", "NICK dan6", "NICK dan7", "NICK dan8", "NICK dan9"],
+            tried
+        );
+    }
+
+    #[test]
+    fn welcome_test() {
+        let mut nicks = Nicks::new("dan".to_string(), vec![], false);
+        nicks.handle(&msg(":irc.example.com 432 * dan :Erroneus nickname")).unwrap();
+        assert_eq!("dan_", nicks.current());
+
+        nicks.handle(&msg(":irc.example.com 001 dan_ :Welcome")).unwrap();
+        assert!(!nicks.has_wanted());
+
+        // once registered a refusal only means a regain didn't work
+        assert!(nicks.handle(&msg(":irc.example.com 433 dan_ dan :Nickname is already in use")).unwrap().is_empty());
+        assert_eq!("dan_", nicks.current());
+    }
+
+    fn isupport(tokens: &str) -> ISupport {
+        let mut isupport = ISupport::new();
+
+        for token in tokens.split(' ') {
+            isupport.token(token);
+        }
+
+        isupport
+    }
+
+    fn registered_as_alternate(regain: bool) -> Nicks {
+        let mut nicks = Nicks::new("dan".to_string(), vec![], regain);
+        nicks.handle(&msg(":irc.example.com 433 * dan :Nickname is already in use")).unwrap();
+        nicks.handle(&msg(":irc.example.com 001 dan_ :Welcome")).unwrap();
+        nicks
+    }
+
+    #[test]
+    fn monitor_test() {
+        let mut nicks = registered_as_alternate(true);
+
+        let replies = nicks.set_isupport(&isupport("MONITOR=100 NICKLEN=30"));
+        assert_eq!(vec!["MONITOR + dan"], lines(replies));
+
+        // no point asking twice
+        assert!(nicks.set_isupport(&isupport("MONITOR=100")).is_empty());
+
+        let replies = nicks.handle(&msg(":irc.example.com 731 dan_ :dan")).unwrap();
+        assert_eq!(vec!["NICK dan"], lines(replies));
+
+        let replies = nicks.handle(&msg(":dan_!d@localhost NICK dan")).unwrap();
+        assert_eq!(vec!["MONITOR - dan"], lines(replies));
+        assert!(nicks.has_wanted());
+    }
+
+    #[test]
+    fn watch_test() {
+        let mut nicks = registered_as_alternate(true);
+
+        assert!(nicks.set_isupport(&isupport("NICKLEN=30")).is_empty());
+
+        assert_eq!(vec!["NICK dan"], lines(nicks.handle(&msg(":Dan!d@elsewhere QUIT :bye")).unwrap()));
+        assert_eq!(vec!["NICK dan"], lines(nicks.handle(&msg(":dan!d@elsewhere NICK dan_away")).unwrap()));
+        assert!(nicks.handle(&msg(":dan!d@elsewhere NICK DAN")).unwrap().is_empty());
+        assert!(nicks.handle(&msg(":alice!a@elsewhere QUIT :bye")).unwrap().is_empty());
+
+        // the same nick under rfc1459, but not ascii
+        let mut nicks = Nicks::new("dan[m]".to_string(), vec![], true);
+        nicks.handle(&msg(":irc.example.com 433 * dan[m] :Nickname is already in use")).unwrap();
+        nicks.handle(&msg(":irc.example.com 001 dan[m]_ :Welcome")).unwrap();
+        assert_eq!(vec!["NICK dan[m]"], lines(nicks.handle(&msg(":DAN{M}!d@elsewhere QUIT :bye")).unwrap()));
+
+        nicks.set_isupport(&isupport("CASEMAPPING=ascii"));
+        assert!(nicks.handle(&msg(":DAN{M}!d@elsewhere QUIT :bye")).unwrap().is_empty());
+
+        let mut nicks = registered_as_alternate(false);
+        assert!(nicks.handle(&msg(":dan!d@elsewhere QUIT :bye")).unwrap().is_empty());
+    }
+}
@@ -2,15 +2,46 @@ use tokio_util::codec::Encoder;
 use tokio_util::codec::Decoder;
 
 use bytes::{Buf, BufMut, BytesMut};
-use std::{cmp, fmt, io, str, usize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::{cmp, fmt, io, str};
 
-use crate::protocol::wire::RawMsg;
+pub use encoding_rs::Encoding;
+
+use tracing::trace;
+
+use crate::casemap::{CaseMapped, CaseMapping};
+use crate::protocol::error::{ParamError, ParseError};
+use crate::protocol::redact::redacted;
+use crate::protocol::wire::{RawMsg, RawMsgRef};
+
+/// The most a message body may take up, including the trailing CR-LF
+pub const MAX_BODY_LENGTH: usize = 512;
+
+/// The most the IRCv3 tag section may take up, including the leading `@`
+/// and the space separating it from the body
+pub const MAX_TAGS_LENGTH: usize = 8191;
+
+// the RFC 2812 channel prefixes, since the codec never sees CHANTYPES
+const CHANNEL_PREFIXES: &[u8] = b"#&+!";
+
+/// How incoming lines that aren't valid UTF-8 are handled
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Decoding {
+    /// Invalid UTF-8 is an error
+    Strict,
+    /// Invalid sequences are replaced with U+FFFD
+    Lossy,
+    /// Lines that aren't valid UTF-8 are decoded with a legacy encoding
+    /// instead, e.g. `encoding_rs::WINDOWS_1252`
+    Fallback(&'static Encoding),
+}
 
 /// A simple `Codec` implementation that splits up data into lines, and
 /// the parses the result into RawMsg's
 /// This is largely a complete copy-paste of upstream LinesCodec with 
 /// minor changes at this point to auto-encode/decode into RawMsg
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct IrcCodec {
     // Stored index of the next index to examine for a `\n` character.
     // This is used to optimize searching.
@@ -20,25 +51,180 @@ pub struct IrcCodec {
     // only look at `de\n` before returning.
     next_index: usize,
 
-    /// The maximum length for a given line. If `usize::MAX`, lines will be
-    /// read until a `\n` character is reached.
-    max_length: usize,
+    /// The maximum length of the IRCv3 tag section, including the `@` and
+    /// the space that ends it.
+    max_tags_length: usize,
+
+    /// The maximum length of the rest of the line, including the CR-LF.
+    max_body_length: usize,
 
     /// Are we currently discarding the remainder of a line which was over
     /// the length limit?
     is_discarding: bool,
+
+    /// Was it the tag section of the line being discarded that was too long?
+    discarding_tags: bool,
+
+    /// What to do with lines that aren't UTF-8.
+    decoding: Decoding,
+
+    /// Channels or nicks, by the server's case mapping, whose messages are
+    /// always in a legacy encoding, both ways.
+    target_encodings: HashMap<CaseMapped, &'static Encoding>,
+
+    /// How the server compares the targets above.
+    casemapping: CaseMapping,
 }
 
 impl IrcCodec {
     pub fn new() -> IrcCodec {
+        IrcCodec::with_limits(MAX_TAGS_LENGTH, MAX_BODY_LENGTH)
+    }
+
+    /// A codec with its own limits on the tag section and the body, for
+    /// servers that stray from the 8191/512 the specs ask for.
+    pub fn with_limits(max_tags_length: usize, max_body_length: usize) -> IrcCodec {
         IrcCodec {
             next_index: 0,
-            max_length: 512, // the IRC spec says 512
+            max_tags_length,
+            max_body_length,
             is_discarding: false,
+            discarding_tags: false,
+            decoding: Decoding::Strict,
+            target_encodings: HashMap::new(),
+            casemapping: CaseMapping::default(),
+        }
+    }
+
+    pub fn set_decoding(&mut self, decoding: Decoding) {
+        self.decoding = decoding;
+    }
+
+    /// Transcodes everything to and from `target` with `encoding`, whatever
+    /// the decoding strategy is. Sending `target` anything the encoding
+    /// can't represent fails with IrcCodecError::Unencodable, rather than
+    /// going out as `&#NNNN;` escapes.
+    ///
+    /// `target` may also be a nick, which covers private messages from it.
+    pub fn set_target_encoding(&mut self, target: &str, encoding: &'static Encoding) {
+        self.target_encodings.insert(CaseMapped::new(target, self.casemapping), encoding);
+    }
+
+    /// Compares targets by the server's CASEMAPPING from now on
+    pub fn set_casemapping(&mut self, casemapping: CaseMapping) {
+        if casemapping == self.casemapping {
+            return;
+        }
+
+        self.casemapping = casemapping;
+        self.target_encodings = self.target_encodings
+            .drain()
+            .map(|(target, encoding)| (CaseMapped::new(target.as_str(), casemapping), encoding))
+            .collect();
+    }
+
+    fn target_encoding(&self, target: &[u8]) -> Option<&'static Encoding> {
+        if self.target_encodings.is_empty() {
+            return None;
+        }
+
+        let target = CaseMapped::new(&String::from_utf8_lossy(target), self.casemapping);
+        self.target_encodings.get(&target).copied()
+    }
+
+    fn decode_text<'a>(&self, line: &'a [u8]) -> Result<Cow<'a, str>, io::Error> {
+        if let Some(encoding) = target_of(line).and_then(|t| self.target_encoding(t)) {
+            return Ok(encoding.decode_without_bom_handling(line).0);
+        }
+
+        match self.decoding {
+            Decoding::Strict => utf8(line).map(Cow::Borrowed),
+            Decoding::Lossy => Ok(String::from_utf8_lossy(line)),
+            Decoding::Fallback(encoding) => match str::from_utf8(line) {
+                Ok(line) => Ok(Cow::Borrowed(line)),
+                Err(_) => Ok(encoding.decode_without_bom_handling(line).0),
+            },
+        }
+    }
+
+    // the whole line can be as long as both limits together
+    fn max_line_length(&self) -> usize {
+        self.max_tags_length.saturating_add(self.max_body_length)
+    }
+
+    fn check_lengths(&self, line: &[u8]) -> Result<(), IrcCodecError> {
+        let tags_length = tags_length(line);
+
+        if tags_length > self.max_tags_length {
+            return Err(IrcCodecError::MaxTagLengthExceeded);
+        }
+
+        if line.len() - tags_length + 2 > self.max_body_length {
+            return Err(IrcCodecError::MaxLineLengthExceeded);
+        }
+
+        Ok(())
+    }
+
+    fn decode_line(&self, line: &[u8]) -> Result<RawMsg, IrcCodecError> {
+        let line = without_carriage_return(line);
+        self.check_lengths(line)?;
+        let line = self.decode_text(line)?;
+        let msg = RawMsgRef::parse(&line)?.to_owned();
+
+        trace!(target: "rust_irc::raw", "<< {}", redacted(&msg));
+
+        Ok(msg)
+    }
+
+    fn overflow_error(&self) -> IrcCodecError {
+        if self.discarding_tags {
+            IrcCodecError::MaxTagLengthExceeded
+        } else {
+            IrcCodecError::MaxLineLengthExceeded
         }
     }
 }
 
+// the channel or nick a line is from, without decoding the line first:
+// the first param, or the second for numerics where the first is our nick.
+// Anything not sent to a channel is keyed on the sender's nick instead.
+fn target_of(line: &[u8]) -> Option<&[u8]> {
+    let mut words = line.split(|b| *b == b' ').filter(|w| !w.is_empty());
+
+    let mut word = words.next()?;
+    if word[0] == b'@' {
+        word = words.next()?;
+    }
+
+    let mut source = None;
+    if word[0] == b':' {
+        source = word[1..].split(|b| *b == b'!').next();
+        word = words.next()?;
+    }
+
+    if word.len() == 3 && word.iter().all(u8::is_ascii_digit) {
+        words.next()?;
+    }
+
+    let target = words.next()?;
+    let target = target.strip_prefix(b":").unwrap_or(target);
+
+    match (source, target.first()) {
+        (Some(nick), first) if !first.is_some_and(|b| CHANNEL_PREFIXES.contains(b)) => Some(nick),
+        _ => Some(target),
+    }
+}
+
+// length of the tag section, including the @ and the space that ends it
+fn tags_length(line: &[u8]) -> usize {
+    if line.first() == Some(&b'@') {
+        line.iter().position(|b| *b == b' ').map_or(line.len(), |i| i + 1)
+    } else {
+        0
+    }
+}
+
 fn utf8(buf: &[u8]) -> Result<&str, io::Error> {
     str::from_utf8(buf)
         .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Unable to decode input as UTF8"))
@@ -58,9 +244,10 @@ impl Decoder for IrcCodec {
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<RawMsg>, IrcCodecError> {
         loop {
-            // Determine how far into the buffer we'll search for a newline. If
-            // there's no max_length set, we'll read to the end of the buffer.
-            let read_to = cmp::min(self.max_length.saturating_add(1), buf.len());
+            // Determine how far into the buffer we'll search for a newline.
+            // If the limits are `usize::MAX`, we'll read to the end of the buffer.
+            let max_line_length = self.max_line_length();
+            let read_to = cmp::min(max_line_length, buf.len());
 
             let newline_offset = buf[self.next_index..read_to]
                 .iter()
@@ -82,7 +269,7 @@ impl Decoder for IrcCodec {
                     buf.advance(read_to);
                     self.next_index = 0;
                     if buf.is_empty() {
-                        return Err(IrcCodecError::MaxLineLengthExceeded);
+                        return Err(self.overflow_error());
                     }
                 }
                 (false, Some(offset)) => {
@@ -91,22 +278,18 @@ impl Decoder for IrcCodec {
                     self.next_index = 0;
                     let line = buf.split_to(newline_index + 1);
                     let line = &line[..line.len() - 1];
-                    let line = without_carriage_return(line);
-                    let line = utf8(line)?;
-                    let line = line.to_string();
 
-                    println!("Received {}", line);
-                    let msg = RawMsg::from_string(line);
-
-                    //return Ok(Some(line.to_string()));
-                    return Ok(Some(msg));
+                    return self.decode_line(line).map(Some);
                 }
-                (false, None) if buf.len() > self.max_length => {
+                (false, None) if buf.len() >= max_line_length => {
                     // Reached the maximum length without finding a
                     // newline, return an error and start discarding on the
-                    // next call.
+                    // next call. If the tags haven't even ended yet it's them
+                    // that are too long.
                     self.is_discarding = true;
-                    return Err(IrcCodecError::MaxLineLengthExceeded);
+                    self.discarding_tags = buf.first() == Some(&b'@')
+                        && !buf[..cmp::min(self.max_tags_length, buf.len())].contains(&b' ');
+                    return Err(self.overflow_error());
                 }
                 (false, None) => {
                     // We didn't find a line or reach the length limit, so the next
@@ -127,13 +310,9 @@ impl Decoder for IrcCodec {
                     None
                 } else {
                     let line = buf.split_to(buf.len());
-                    let line = without_carriage_return(&line);
-                    let line = utf8(line)?;
                     self.next_index = 0;
-                    //Some(line.to_string())
 
-                    let msg = RawMsg::from_string(line.to_string());
-                    Some(msg)
+                    Some(self.decode_line(&line)?)
                 }
             }
         })
@@ -145,12 +324,31 @@ impl Encoder for IrcCodec {
     type Error = IrcCodecError;
 
     fn encode(&mut self, msg: RawMsg, buf: &mut BytesMut) -> Result<(), IrcCodecError> {
+        msg.validate()?;
+
         let line = msg.to_string();
 
-        println!("Sending: {}", line);
+        // anything that would end the line early lets the rest of it be read
+        // as a second command by the server
+        if let Some(offset) = line.bytes().position(|b| b == b'\r' || b == b'\n' || b == b'\0') {
+            return Err(IrcCodecError::IllegalByte { byte: line.as_bytes()[offset], offset });
+        }
+
+        trace!(target: "rust_irc::raw", ">> {}", redacted(&msg));
 
-        buf.reserve(line.len() + 1);
-        buf.put(line.as_bytes());
+        let bytes = match msg.params.first().and_then(|t| self.target_encoding(t.as_bytes())) {
+            Some(encoding) => match encoding.encode(&line) {
+                // encoding_rs would have turned the rest into &#NNNN; escapes
+                (_, _, true) => return Err(IrcCodecError::Unencodable { encoding: encoding.name() }),
+                (bytes, _, false) => bytes,
+            },
+            None => Cow::Borrowed(line.as_bytes()),
+        };
+
+        self.check_lengths(&bytes)?;
+
+        buf.reserve(bytes.len() + 2);
+        buf.put(&bytes[..]);
         buf.put_u8(b'\r');
         buf.put_u8(b'\n');
         Ok(())
@@ -166,8 +364,19 @@ impl Default for IrcCodec {
 /// An error occured while encoding or decoding a line.
 #[derive(Debug)]
 pub enum IrcCodecError {
-    /// The maximum line length was exceeded.
+    /// The maximum length of the message body was exceeded.
     MaxLineLengthExceeded,
+    /// The maximum length of the tag section was exceeded.
+    MaxTagLengthExceeded,
+    /// An outgoing message contains a CR, LF or NUL.
+    IllegalByte { byte: u8, offset: usize },
+    /// A line was read but isn't a valid IRC message.
+    Parse(ParseError),
+    /// An outgoing message has a param that can't be sent as-is.
+    InvalidParam(ParamError),
+    /// An outgoing message has characters its target's encoding can't
+    /// represent.
+    Unencodable { encoding: &'static str },
     /// An IO error occured.
     Io(io::Error),
 }
@@ -175,7 +384,14 @@ pub enum IrcCodecError {
 impl fmt::Display for IrcCodecError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            IrcCodecError::MaxLineLengthExceeded => write!(f, "max line length exceeded"),
+            IrcCodecError::MaxLineLengthExceeded => write!(f, "max body length exceeded"),
+            IrcCodecError::MaxTagLengthExceeded => write!(f, "max tag length exceeded"),
+            IrcCodecError::IllegalByte { byte, offset } => {
+                write!(f, "illegal byte {:#04x} at offset {}", byte, offset)
+            }
+            IrcCodecError::Parse(e) => write!(f, "{}", e),
+            IrcCodecError::InvalidParam(e) => write!(f, "{}", e),
+            IrcCodecError::Unencodable { encoding } => write!(f, "message can't be encoded as {}", encoding),
             IrcCodecError::Io(e) => write!(f, "{}", e),
         }
     }
@@ -187,4 +403,233 @@ impl From<io::Error> for IrcCodecError {
     }
 }
 
+impl From<ParseError> for IrcCodecError {
+    fn from(e: ParseError) -> IrcCodecError {
+        IrcCodecError::Parse(e)
+    }
+}
+
+impl From<ParamError> for IrcCodecError {
+    fn from(e: ParamError) -> IrcCodecError {
+        IrcCodecError::InvalidParam(e)
+    }
+}
+
 impl std::error::Error for IrcCodecError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::tags::{TagValue, Tags};
+
+    #[test]
+    fn decode_test() {
+        let mut codec = IrcCodec::new();
+        let mut buf = BytesMut::from(&b"PING :irc.example.com\r\nPRIVMSG #chan"[..]);
+
+        let msg = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!("PING", msg.command);
+        assert_eq!(vec!["irc.example.com"], msg.params);
+
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn decode_parse_error_test() {
+        let mut codec = IrcCodec::new();
+        let mut buf = BytesMut::from(&b"@id=1\r\nPING :irc.example.com\r\n"[..]);
+
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(IrcCodecError::Parse(ParseError::MissingCommand { offset: 5 }))
+        ));
+
+        // the bad line is consumed, so the next one decodes fine
+        assert_eq!("PING", codec.decode(&mut buf).unwrap().unwrap().command);
+    }
+
+    #[test]
+    fn decode_long_tags_test() {
+        let mut codec = IrcCodec::new();
+        let line = format!("@+example.com/foo={} :dan!d@localhost PRIVMSG #chan :hi\r\n", "a".repeat(4000));
+        let mut buf = BytesMut::from(line.as_bytes());
+
+        let msg = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!("PRIVMSG", msg.command);
+    }
+
+    #[test]
+    fn decode_limits_test() {
+        let mut codec = IrcCodec::with_limits(64, 32);
+
+        let tags = format!("@id={} PING :x\r\n", "a".repeat(64));
+        let mut buf = BytesMut::from(tags.as_bytes());
+        assert!(matches!(codec.decode(&mut buf), Err(IrcCodecError::MaxTagLengthExceeded)));
+
+        let body = format!("@id=1 PRIVMSG #chan :{}\r\n", "a".repeat(32));
+        let mut buf = BytesMut::from(body.as_bytes());
+        assert!(matches!(codec.decode(&mut buf), Err(IrcCodecError::MaxLineLengthExceeded)));
+
+        let fits = format!("@id={} PRIVMSG #chan :{}\r\n", "a".repeat(59), "a".repeat(9));
+        let mut buf = BytesMut::from(fits.as_bytes());
+        assert_eq!("PRIVMSG", codec.decode(&mut buf).unwrap().unwrap().command);
+    }
+
+    #[test]
+    fn decode_overflow_test() {
+        let mut codec = IrcCodec::with_limits(64, 32);
+
+        let mut buf = BytesMut::from(format!("@id={}", "a".repeat(100)).as_bytes());
+        assert!(matches!(codec.decode(&mut buf), Err(IrcCodecError::MaxTagLengthExceeded)));
+
+        let mut codec = IrcCodec::with_limits(64, 32);
+
+        let mut buf = BytesMut::from(format!("PRIVMSG #chan :{}", "a".repeat(100)).as_bytes());
+        assert!(matches!(codec.decode(&mut buf), Err(IrcCodecError::MaxLineLengthExceeded)));
+
+        // the rest of the overlong line is thrown away
+        buf.extend_from_slice(b"aaaa\r\nPING :x\r\n");
+        assert_eq!("PING", codec.decode(&mut buf).unwrap().unwrap().command);
+    }
+
+    #[test]
+    fn encode_test() {
+        let mut codec = IrcCodec::new();
+        let mut buf = BytesMut::new();
+
+        let msg = RawMsg::new("PRIVMSG".to_string(), Some(vec!["#chan".to_string(), "hi there".to_string()]));
+        codec.encode(msg, &mut buf).unwrap();
+
+        assert_eq!(&b"PRIVMSG #chan :hi there\r\n"[..], &buf[..]);
+    }
+
+    #[test]
+    fn encode_illegal_byte_test() {
+        let mut codec = IrcCodec::new();
+        let mut buf = BytesMut::new();
+
+        let msg = RawMsg::new("PRIVMSG".to_string(), Some(vec!["#chan".to_string(), "hi\r\nQUIT".to_string()]));
+
+        assert!(matches!(
+            codec.encode(msg, &mut buf),
+            Err(IrcCodecError::IllegalByte { byte: b'\r', offset: 16 })
+        ));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn encode_length_test() {
+        let mut codec = IrcCodec::new();
+        let mut buf = BytesMut::new();
+
+        // 8 bytes of "PRIVMSG ", 6 of "#chan " and 2 of CR-LF
+        let fits = RawMsg::new("PRIVMSG".to_string(), Some(vec!["#chan".to_string(), "a".repeat(496)]));
+        codec.encode(fits, &mut buf).unwrap();
+
+        let long = RawMsg::new("PRIVMSG".to_string(), Some(vec!["#chan".to_string(), "a".repeat(497)]));
+        assert!(matches!(codec.encode(long, &mut buf), Err(IrcCodecError::MaxLineLengthExceeded)));
+
+        let mut tagged = RawMsg::new("PRIVMSG".to_string(), Some(vec!["#chan".to_string(), "a".repeat(496)]));
+        let mut tags = Tags::new();
+        tags.insert("+example.com/foo".to_string(), TagValue::String("b".repeat(8000)));
+        tagged.tags = Some(tags);
+        codec.encode(tagged.clone(), &mut buf).unwrap();
+
+        tagged.tags.as_mut().unwrap().insert("+example.com/bar".to_string(), TagValue::String("b".repeat(200)));
+        assert!(matches!(codec.encode(tagged, &mut buf), Err(IrcCodecError::MaxTagLengthExceeded)));
+    }
+
+    #[test]
+    fn decode_strict_test() {
+        let mut codec = IrcCodec::new();
+        let mut buf = BytesMut::from(&b"PRIVMSG #chan :caf\xe9\r\n"[..]);
+
+        assert!(matches!(codec.decode(&mut buf), Err(IrcCodecError::Io(_))));
+    }
+
+    #[test]
+    fn decode_lossy_test() {
+        let mut codec = IrcCodec::new();
+        codec.set_decoding(Decoding::Lossy);
+        let mut buf = BytesMut::from(&b"PRIVMSG #chan :caf\xe9\r\n"[..]);
+
+        assert_eq!("caf\u{fffd}", codec.decode(&mut buf).unwrap().unwrap().params[1]);
+    }
+
+    #[test]
+    fn decode_fallback_test() {
+        let mut codec = IrcCodec::new();
+        codec.set_decoding(Decoding::Fallback(encoding_rs::WINDOWS_1252));
+        let mut buf = BytesMut::from(&b"PRIVMSG #chan :caf\xe9 \x80\r\nPRIVMSG #chan :caf\xc3\xa9\r\n"[..]);
+
+        assert_eq!("café €", codec.decode(&mut buf).unwrap().unwrap().params[1]);
+        assert_eq!("café", codec.decode(&mut buf).unwrap().unwrap().params[1]);
+    }
+
+    #[test]
+    fn target_encoding_test() {
+        let mut codec = IrcCodec::new();
+        codec.set_target_encoding("#Latin", encoding_rs::WINDOWS_1252);
+
+        // valid UTF-8 is still transcoded for the overridden channel
+        let mut buf = BytesMut::from(&b"@id=1 :dan!d@localhost PRIVMSG #latin :caf\xc3\xa9\r\n"[..]);
+        assert_eq!("cafÃ©", codec.decode(&mut buf).unwrap().unwrap().params[1]);
+
+        let mut buf = BytesMut::new();
+        let msg = RawMsg::new("PRIVMSG".to_string(), Some(vec!["#LATIN".to_string(), "café €".to_string()]));
+        codec.encode(msg, &mut buf).unwrap();
+        assert_eq!(&b"PRIVMSG #LATIN :caf\xe9 \x80\r\n"[..], &buf[..]);
+
+        let mut buf = BytesMut::new();
+        let msg = RawMsg::new("PRIVMSG".to_string(), Some(vec!["#chan".to_string(), "café".to_string()]));
+        codec.encode(msg, &mut buf).unwrap();
+        assert_eq!("PRIVMSG #chan café\r\n".as_bytes(), &buf[..]);
+
+        let mut buf = BytesMut::new();
+        let msg = RawMsg::new("PRIVMSG".to_string(), Some(vec!["#latin".to_string(), "日本".to_string()]));
+        assert!(matches!(codec.encode(msg, &mut buf), Err(IrcCodecError::Unencodable { encoding: "windows-1252" })));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn target_casemapping_test() {
+        let mut codec = IrcCodec::new();
+        codec.set_target_encoding("#Foo[", encoding_rs::WINDOWS_1252);
+
+        // the same channel under rfc1459, but not ascii
+        let mut buf = BytesMut::new();
+        let msg = RawMsg::new("PRIVMSG".to_string(), Some(vec!["#foo{".to_string(), "é".to_string()]));
+        codec.encode(msg, &mut buf).unwrap();
+        assert_eq!(&b"PRIVMSG #foo{ \xe9\r\n"[..], &buf[..]);
+
+        codec.set_casemapping(CaseMapping::Ascii);
+
+        let mut buf = BytesMut::new();
+        let msg = RawMsg::new("PRIVMSG".to_string(), Some(vec!["#foo{".to_string(), "é".to_string()]));
+        codec.encode(msg, &mut buf).unwrap();
+        assert_eq!("PRIVMSG #foo{ é\r\n".as_bytes(), &buf[..]);
+    }
+
+    #[test]
+    fn target_private_message_test() {
+        let mut codec = IrcCodec::new();
+        codec.set_target_encoding("Bob", encoding_rs::WINDOWS_1252);
+
+        // addressed to us, so the sender decides the encoding
+        let mut buf = BytesMut::from(&b":bob!b@localhost PRIVMSG dan :caf\xe9\r\n"[..]);
+        assert_eq!("café", codec.decode(&mut buf).unwrap().unwrap().params[1]);
+
+        // but not in a channel without an override
+        let mut buf = BytesMut::from(&b":bob!b@localhost PRIVMSG #chan :caf\xc3\xa9\r\n"[..]);
+        assert_eq!("café", codec.decode(&mut buf).unwrap().unwrap().params[1]);
+    }
+
+    #[test]
+    fn target_numeric_test() {
+        let mut codec = IrcCodec::new();
+        codec.set_target_encoding("#latin", encoding_rs::WINDOWS_1252);
+
+        let mut buf = BytesMut::from(&b":irc.example.com 332 dan #latin :caf\xe9\r\n"[..]);
+        assert_eq!("café", codec.decode(&mut buf).unwrap().unwrap().params[2]);
+    }
+}
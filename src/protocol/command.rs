@@ -0,0 +1,445 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::protocol::numeric::{numeric_code, Response};
+use crate::protocol::prefix::Prefix;
+use crate::protocol::tags::Tags;
+use crate::protocol::wire::RawMsg;
+
+/*
+ * Typed view over the command and params of a RawMsg. A Command on its own
+ * has no tags or source, so Message carries those alongside it.
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Privmsg { target: String, text: String },
+    Notice { target: String, text: String },
+    // extended-join adds the account and real name after the channel
+    Join {
+        channels: Vec<String>,
+        keys: Vec<String>,
+        account: Option<String>,
+        real_name: Option<String>,
+    },
+    Part { channels: Vec<String>, reason: Option<String> },
+    Kick { channel: String, user: String, comment: Option<String> },
+    Mode { target: String, modes: Vec<String> },
+    Topic { channel: String, topic: Option<String> },
+    Nick { nick: String },
+    Quit { reason: Option<String> },
+    // the target is only present on server -> client CAP messages
+    Cap { target: Option<String>, subcommand: String, params: Vec<String> },
+    Authenticate { data: String },
+    Ping { token: String, server: Option<String> },
+    Pong { server: Option<String>, token: String },
+    Response { response: Response, params: Vec<String> },
+    // numerics missing from the catalogue
+    Numeric { code: u16, params: Vec<String> },
+    Other { command: String, params: Vec<String> },
+}
+
+const CAP_SUBCOMMANDS: &[&str] = &["LS", "LIST", "REQ", "ACK", "NAK", "END", "NEW", "DEL"];
+
+impl Command {
+    /// The command name as it appears on the wire
+    pub fn name(&self) -> String {
+        match self {
+            Command::Privmsg { .. } => "PRIVMSG".to_string(),
+            Command::Notice { .. } => "NOTICE".to_string(),
+            Command::Join { .. } => "JOIN".to_string(),
+            Command::Part { .. } => "PART".to_string(),
+            Command::Kick { .. } => "KICK".to_string(),
+            Command::Mode { .. } => "MODE".to_string(),
+            Command::Topic { .. } => "TOPIC".to_string(),
+            Command::Nick { .. } => "NICK".to_string(),
+            Command::Quit { .. } => "QUIT".to_string(),
+            Command::Cap { .. } => "CAP".to_string(),
+            Command::Authenticate { .. } => "AUTHENTICATE".to_string(),
+            Command::Ping { .. } => "PING".to_string(),
+            Command::Pong { .. } => "PONG".to_string(),
+            Command::Response { response, .. } => response.to_string(),
+            Command::Numeric { code, .. } => format!("{:03}", code),
+            Command::Other { command, .. } => command.clone(),
+        }
+    }
+}
+
+/// A known command arrived with a param count it can't have
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandError {
+    pub command: String,
+    pub min: usize,
+    pub max: usize,
+    pub found: usize,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} expects between {} and {} params, found {}",
+            self.command, self.min, self.max, self.found
+        )
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+fn check_params(command: &str, params: &[String], min: usize, max: usize) -> Result<(), CommandError> {
+    if params.len() < min || params.len() > max {
+        return Err(CommandError {
+            command: command.to_string(),
+            min,
+            max,
+            found: params.len(),
+        });
+    }
+
+    Ok(())
+}
+
+fn split_list(x: String) -> Vec<String> {
+    x.split(',').map(|s| s.to_string()).collect()
+}
+
+/// Only the command and params are kept, the tags and source being
+/// dropped, which Message keeps. Known command names come back from
+/// `Into<RawMsg>` in uppercase, as IRC doesn't care about their case;
+/// anything in Other is left as it was sent.
+impl TryFrom<RawMsg> for Command {
+    type Error = CommandError;
+
+    fn try_from(msg: RawMsg) -> Result<Command, CommandError> {
+        let name = msg.command.to_ascii_uppercase();
+        let params = msg.params;
+
+        if let Some(code) = numeric_code(&name) {
+            return match Response::from_code(code) {
+                Some(response) => Ok(Command::Response { response, params }),
+                None => Ok(Command::Numeric { code, params }),
+            };
+        }
+
+        let command = match name.as_ref() {
+            "PRIVMSG" | "NOTICE" => {
+                check_params(&name, &params, 2, 2)?;
+                let mut i = params.into_iter();
+                let target = i.next().unwrap_or_default();
+                let text = i.next().unwrap_or_default();
+
+                if name == "PRIVMSG" {
+                    Command::Privmsg { target, text }
+                } else {
+                    Command::Notice { target, text }
+                }
+            }
+            "JOIN" => {
+                check_params(&name, &params, 1, 3)?;
+                let extended = params.len() == 3;
+                let mut i = params.into_iter();
+                let channels = split_list(i.next().unwrap_or_default());
+
+                if extended {
+                    Command::Join { channels, keys: vec![], account: i.next(), real_name: i.next() }
+                } else {
+                    Command::Join { channels, keys: i.next().map(split_list).unwrap_or_default(), account: None, real_name: None }
+                }
+            }
+            "PART" => {
+                check_params(&name, &params, 1, 2)?;
+                let mut i = params.into_iter();
+                let channels = split_list(i.next().unwrap_or_default());
+                Command::Part { channels, reason: i.next() }
+            }
+            "KICK" => {
+                check_params(&name, &params, 2, 3)?;
+                let mut i = params.into_iter();
+                let channel = i.next().unwrap_or_default();
+                let user = i.next().unwrap_or_default();
+                Command::Kick { channel, user, comment: i.next() }
+            }
+            "MODE" => {
+                check_params(&name, &params, 1, usize::MAX)?;
+                let mut i = params.into_iter();
+                let target = i.next().unwrap_or_default();
+                Command::Mode { target, modes: i.collect() }
+            }
+            "TOPIC" => {
+                check_params(&name, &params, 1, 2)?;
+                let mut i = params.into_iter();
+                let channel = i.next().unwrap_or_default();
+                Command::Topic { channel, topic: i.next() }
+            }
+            "NICK" => {
+                check_params(&name, &params, 1, 1)?;
+                Command::Nick { nick: params.into_iter().next().unwrap_or_default() }
+            }
+            "QUIT" => {
+                check_params(&name, &params, 0, 1)?;
+                Command::Quit { reason: params.into_iter().next() }
+            }
+            "CAP" => {
+                check_params(&name, &params, 1, usize::MAX)?;
+                let has_target = params.len() >= 2
+                    && CAP_SUBCOMMANDS.contains(&params[1].to_ascii_uppercase().as_ref());
+                let mut i = params.into_iter();
+                let target = if has_target { i.next() } else { None };
+                let subcommand = i.next().unwrap_or_default();
+                Command::Cap { target, subcommand, params: i.collect() }
+            }
+            "AUTHENTICATE" => {
+                check_params(&name, &params, 1, 1)?;
+                Command::Authenticate { data: params.into_iter().next().unwrap_or_default() }
+            }
+            "PING" => {
+                check_params(&name, &params, 1, 2)?;
+                let mut i = params.into_iter();
+                let token = i.next().unwrap_or_default();
+                Command::Ping { token, server: i.next() }
+            }
+            "PONG" => {
+                check_params(&name, &params, 1, 2)?;
+                let mut i = params.into_iter();
+                let first = i.next().unwrap_or_default();
+                match i.next() {
+                    Some(token) => Command::Pong { server: Some(first), token },
+                    None => Command::Pong { server: None, token: first },
+                }
+            }
+            _ => Command::Other { command: msg.command, params },
+        };
+
+        Ok(command)
+    }
+}
+
+impl From<Command> for RawMsg {
+    fn from(command: Command) -> RawMsg {
+        let name = command.name();
+
+        let params = match command {
+            Command::Privmsg { target, text } | Command::Notice { target, text } => vec![target, text],
+            Command::Join { channels, keys, account, real_name } => {
+                let mut params = vec![channels.join(",")];
+                if !keys.is_empty() {
+                    params.push(keys.join(","));
+                }
+                params.extend(account);
+                params.extend(real_name);
+                params
+            }
+            Command::Part { channels, reason } => {
+                let mut params = vec![channels.join(",")];
+                params.extend(reason);
+                params
+            }
+            Command::Kick { channel, user, comment } => {
+                let mut params = vec![channel, user];
+                params.extend(comment);
+                params
+            }
+            Command::Mode { target, modes } => {
+                let mut params = vec![target];
+                params.extend(modes);
+                params
+            }
+            Command::Topic { channel, topic } => {
+                let mut params = vec![channel];
+                params.extend(topic);
+                params
+            }
+            Command::Nick { nick } => vec![nick],
+            Command::Quit { reason } => reason.into_iter().collect(),
+            Command::Cap { target, subcommand, params: rest } => {
+                let mut params: Vec<String> = target.into_iter().collect();
+                params.push(subcommand);
+                params.extend(rest);
+                params
+            }
+            Command::Authenticate { data } => vec![data],
+            Command::Ping { token, server } => {
+                let mut params = vec![token];
+                params.extend(server);
+                params
+            }
+            Command::Pong { server, token } => {
+                let mut params: Vec<String> = server.into_iter().collect();
+                params.push(token);
+                params
+            }
+            Command::Response { params, .. }
+            | Command::Numeric { params, .. }
+            | Command::Other { params, .. } => params,
+        };
+
+        RawMsg::new(name, Some(params))
+    }
+}
+
+/// A Command with the tags and source of its message, so nothing but the
+/// case of a known command name is lost converting to and from RawMsg
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub tags: Option<Tags>,
+    pub source: Option<Prefix>,
+    pub command: Command,
+}
+
+impl TryFrom<RawMsg> for Message {
+    type Error = CommandError;
+
+    fn try_from(mut msg: RawMsg) -> Result<Message, CommandError> {
+        let tags = msg.tags.take();
+        let source = msg.source.take();
+
+        Ok(Message {
+            tags,
+            source,
+            command: Command::try_from(msg)?,
+        })
+    }
+}
+
+impl From<Message> for RawMsg {
+    fn from(message: Message) -> RawMsg {
+        let mut msg = RawMsg::from(message.command);
+        msg.tags = message.tags;
+        msg.source = message.source;
+        msg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(line: &str) -> Command {
+        let msg: RawMsg = line.parse().unwrap();
+        let command = Command::try_from(msg.clone()).unwrap();
+        let back: RawMsg = command.clone().into();
+
+        assert_eq!(msg.command.to_ascii_uppercase(), back.command);
+        assert_eq!(msg.params, back.params);
+
+        command
+    }
+
+    #[test]
+    fn privmsg_test() {
+        let command = round_trip(":dan!d@localhost PRIVMSG #chan :Hey what's up!");
+
+        assert_eq!(Command::Privmsg {
+            target: "#chan".to_string(),
+            text: "Hey what's up!".to_string(),
+        }, command);
+    }
+
+    #[test]
+    fn join_test() {
+        let command = round_trip("JOIN #foo,#bar,#baz fubar,secret");
+
+        assert_eq!(Command::Join {
+            channels: vec!["#foo".to_string(), "#bar".to_string(), "#baz".to_string()],
+            keys: vec!["fubar".to_string(), "secret".to_string()],
+            account: None,
+            real_name: None,
+        }, command);
+    }
+
+    #[test]
+    fn extended_join_test() {
+        let command = round_trip(":dan!d@localhost JOIN #chan dan :Dan Smith");
+
+        assert_eq!(Command::Join {
+            channels: vec!["#chan".to_string()],
+            keys: vec![],
+            account: Some("dan".to_string()),
+            real_name: Some("Dan Smith".to_string()),
+        }, command);
+    }
+
+    #[test]
+    fn cap_server_test() {
+        let command = round_trip(":irc.example.com CAP * LS * :multi-prefix extended-join sasl");
+
+        assert_eq!(Command::Cap {
+            target: Some("*".to_string()),
+            subcommand: "LS".to_string(),
+            params: vec!["*".to_string(), "multi-prefix extended-join sasl".to_string()],
+        }, command);
+    }
+
+    #[test]
+    fn cap_client_test() {
+        let command = round_trip("CAP LS 302");
+
+        assert_eq!(Command::Cap {
+            target: None,
+            subcommand: "LS".to_string(),
+            params: vec!["302".to_string()],
+        }, command);
+    }
+
+    #[test]
+    fn pong_test() {
+        assert_eq!(Command::Pong {
+            server: Some("irc.example.com".to_string()),
+            token: "12345".to_string(),
+        }, round_trip(":irc.example.com PONG irc.example.com 12345"));
+    }
+
+    #[test]
+    fn response_test() {
+        let command = round_trip(":irc.example.com 001 dan :Welcome to the network");
+
+        assert!(matches!(command, Command::Response { response: Response::RPL_WELCOME, .. }));
+    }
+
+    #[test]
+    fn unknown_numeric_test() {
+        let command = round_trip(":irc.example.com 999 dan :Something new");
+
+        assert!(matches!(command, Command::Numeric { code: 999, .. }));
+    }
+
+    #[test]
+    fn other_test() {
+        let command = round_trip(":irc.example.com WALLOPS :hello");
+
+        assert_eq!(Command::Other {
+            command: "WALLOPS".to_string(),
+            params: vec!["hello".to_string()],
+        }, command);
+    }
+
+    #[test]
+    fn message_test() {
+        let msg: RawMsg = "@time=2020-01-01T00:00:00.000Z;+draft/reply=abc :dan!d@localhost PRIVMSG #chan :hi".parse().unwrap();
+        let message = Message::try_from(msg.clone()).unwrap();
+
+        assert_eq!("dan", message.source.as_ref().unwrap().nick);
+        assert_eq!(msg, RawMsg::from(message));
+
+        // the command alone drops the tags and source, and the case of the
+        // command name
+        let lower: RawMsg = "@a=b :dan!d@localhost privmsg #chan :hi".parse().unwrap();
+        let back = RawMsg::from(Command::try_from(lower.clone()).unwrap());
+        assert_eq!((None, None, "PRIVMSG"), (back.tags, back.source, back.command.as_str()));
+        assert_eq!(lower.params, back.params);
+
+        assert_eq!("PRIVMSG", RawMsg::from(Message::try_from(lower).unwrap()).command);
+    }
+
+    #[test]
+    fn missing_params_test() {
+        let msg = RawMsg::new("KICK".to_string(), Some(vec!["#chan".to_string()]));
+
+        assert_eq!(Err(CommandError {
+            command: "KICK".to_string(),
+            min: 2,
+            max: 3,
+            found: 1,
+        }), Command::try_from(msg));
+    }
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+
+/// Why a line couldn't be parsed. Every variant carries the byte offset
+/// into the input where the problem was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// Nothing but whitespace
+    EmptyLine { offset: usize },
+    /// NUL is never valid anywhere in a line
+    NulByte { offset: usize },
+    /// Tags or a source were present, but no command followed them
+    MissingCommand { offset: usize },
+    /// The command is neither letters nor a three digit numeric
+    InvalidCommand { offset: usize },
+    /// A tag key is empty or contains characters outside the spec
+    InvalidTagKey { offset: usize },
+    /// A `:` with nothing after it where the source should be
+    EmptySource { offset: usize },
+    /// More than the 15 params the protocol permits
+    TooManyParams { offset: usize },
+}
+
+impl ParseError {
+    pub fn offset(&self) -> usize {
+        match *self {
+            ParseError::EmptyLine { offset }
+            | ParseError::NulByte { offset }
+            | ParseError::MissingCommand { offset }
+            | ParseError::InvalidCommand { offset }
+            | ParseError::InvalidTagKey { offset }
+            | ParseError::EmptySource { offset }
+            | ParseError::TooManyParams { offset } => offset,
+        }
+    }
+
+    // moves the offset along when a section was parsed on its own
+    pub(crate) fn shift(self, by: usize) -> ParseError {
+        match self {
+            ParseError::EmptyLine { offset } => ParseError::EmptyLine { offset: offset + by },
+            ParseError::NulByte { offset } => ParseError::NulByte { offset: offset + by },
+            ParseError::MissingCommand { offset } => ParseError::MissingCommand { offset: offset + by },
+            ParseError::InvalidCommand { offset } => ParseError::InvalidCommand { offset: offset + by },
+            ParseError::InvalidTagKey { offset } => ParseError::InvalidTagKey { offset: offset + by },
+            ParseError::EmptySource { offset } => ParseError::EmptySource { offset: offset + by },
+            ParseError::TooManyParams { offset } => ParseError::TooManyParams { offset: offset + by },
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            ParseError::EmptyLine { .. } => "empty line",
+            ParseError::NulByte { .. } => "NUL byte in line",
+            ParseError::MissingCommand { .. } => "missing command",
+            ParseError::InvalidCommand { .. } => "invalid command",
+            ParseError::InvalidTagKey { .. } => "invalid tag key",
+            ParseError::EmptySource { .. } => "empty source",
+            ParseError::TooManyParams { .. } => "too many params",
+        };
+
+        write!(f, "{} at byte {}", reason, self.offset())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A param that can't be written out as-is without changing the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamError {
+    /// Only the last param may be empty
+    Empty { index: usize },
+    /// Only the last param may contain spaces
+    ContainsSpace { index: usize },
+    /// Only the last param may start with a colon
+    LeadingColon { index: usize },
+    /// More than the 15 params the protocol permits
+    TooMany { count: usize },
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::Empty { index } => write!(f, "param {} is empty", index),
+            ParamError::ContainsSpace { index } => write!(f, "param {} contains a space", index),
+            ParamError::LeadingColon { index } => write!(f, "param {} starts with a colon", index),
+            ParamError::TooMany { count } => write!(f, "{} params is too many", count),
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
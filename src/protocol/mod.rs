@@ -1,4 +1,9 @@
 pub mod codec;
+pub mod command;
+pub mod error;
+pub mod numeric;
 pub mod prefix;
+pub mod redact;
+pub mod split;
 pub mod tags;
 pub mod wire;
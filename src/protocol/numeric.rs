@@ -0,0 +1,313 @@
+use std::fmt;
+
+/*
+ * Catalogue of the numeric replies from RFC 1459, RFC 2812 and the modern
+ * ircdocs specification, along with the parameter layout of each. The ones
+ * RFC 2812 only lists as reserved have no documented params, so they get
+ * whatever old servers sent, or `<client> :<text>` where that's unknown.
+ */
+
+macro_rules! responses {
+    ($($name:ident = $code:expr => $layout:expr,)*) => {
+        #[allow(non_camel_case_types)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[repr(u16)]
+        pub enum Response {
+            $($name = $code,)*
+        }
+
+        impl Response {
+            pub fn from_code(code: u16) -> Option<Response> {
+                match code {
+                    $($code => Some(Response::$name),)*
+                    _ => None,
+                }
+            }
+
+            /// The constant name, e.g. `RPL_WELCOME`
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Response::$name => stringify!($name),)*
+                }
+            }
+
+            /// The params the reply carries, in the ircdocs notation
+            pub fn layout(self) -> &'static str {
+                match self {
+                    $(Response::$name => $layout,)*
+                }
+            }
+        }
+    }
+}
+
+responses! {
+    RPL_WELCOME = 1 => "<client> :Welcome to the <networkname> Network, <nick>[!<user>@<host>]",
+    RPL_YOURHOST = 2 => "<client> :Your host is <servername>, running version <version>",
+    RPL_CREATED = 3 => "<client> :This server was created <datetime>",
+    RPL_MYINFO = 4 => "<client> <servername> <version> <available user modes> <available channel modes> [<channel modes with a parameter>]",
+    RPL_ISUPPORT = 5 => "<client> <1-13 tokens> :are supported by this server",
+    RPL_BOUNCE = 10 => "<client> <hostname> <port> :<info>",
+    RPL_TRACELINK = 200 => "<client> Link <version> <destination> <next server> V<protocol version> <link uptime> <backstream sendq> <upstream sendq>",
+    RPL_TRACECONNECTING = 201 => "<client> Try. <class> <server>",
+    RPL_TRACEHANDSHAKE = 202 => "<client> H.S. <class> <server>",
+    RPL_TRACEUNKNOWN = 203 => "<client> ???? <class> [<client IP address>]",
+    RPL_TRACEOPERATOR = 204 => "<client> Oper <class> <nick>",
+    RPL_TRACEUSER = 205 => "<client> User <class> <nick>",
+    RPL_TRACESERVER = 206 => "<client> Serv <class> <int>S <int>C <server> <nick!user|*!*>@<host|server> V<protocol version>",
+    RPL_TRACESERVICE = 207 => "<client> Service <class> <name> <type> <active type>",
+    RPL_TRACENEWTYPE = 208 => "<client> <newtype> 0 <client name>",
+    RPL_TRACECLASS = 209 => "<client> Class <class> <count>",
+    RPL_TRACERECONNECT = 210 => "<client> :<text>",
+    RPL_STATSLINKINFO = 211 => "<client> <linkname> <sendq> <sent messages> <sent Kbytes> <received messages> <received Kbytes> <time open>",
+    RPL_STATSCOMMANDS = 212 => "<client> <command> <count> [<byte count> <remote count>]",
+    RPL_STATSCLINE = 213 => "<client> C <host> * <name> <port> <class>",
+    RPL_STATSNLINE = 214 => "<client> N <host> * <name> <port> <class>",
+    RPL_STATSILINE = 215 => "<client> I <host> * <host> <port> <class>",
+    RPL_STATSKLINE = 216 => "<client> K <host> * <username> <port> <class>",
+    RPL_STATSQLINE = 217 => "<client> Q <mask> * <name> :<reason>",
+    RPL_STATSYLINE = 218 => "<client> Y <class> <ping frequency> <connect frequency> <max sendq>",
+    RPL_ENDOFSTATS = 219 => "<client> <stats letter> :End of /STATS report",
+    RPL_UMODEIS = 221 => "<client> <user modes>",
+    RPL_SERVICEINFO = 231 => "<client> :<text>",
+    RPL_ENDOFSERVICES = 232 => "<client> :<text>",
+    RPL_SERVICE = 233 => "<client> :<text>",
+    RPL_SERVLIST = 234 => "<client> <name> <server> <mask> <type> <hopcount> <info>",
+    RPL_SERVLISTEND = 235 => "<client> <mask> <type> :End of service listing",
+    RPL_STATSLLINE = 241 => "<client> L <hostmask> * <servername> <maxdepth>",
+    RPL_STATSUPTIME = 242 => "<client> :Server Up <days> days <hours>:<minutes>:<seconds>",
+    RPL_STATSOLINE = 243 => "<client> O <hostmask> * <name>",
+    RPL_STATSHLINE = 244 => "<client> H <hostmask> * <servername>",
+    RPL_STATSCONN = 250 => "<client> :Highest connection count: <count>",
+    RPL_LUSERCLIENT = 251 => "<client> :There are <u> users and <i> invisible on <s> servers",
+    RPL_LUSEROP = 252 => "<client> <ops> :operator(s) online",
+    RPL_LUSERUNKNOWN = 253 => "<client> <connections> :unknown connection(s)",
+    RPL_LUSERCHANNELS = 254 => "<client> <channels> :channels formed",
+    RPL_LUSERME = 255 => "<client> :I have <c> clients and <s> servers",
+    RPL_ADMINME = 256 => "<client> [<server>] :Administrative info",
+    RPL_ADMINLOC1 = 257 => "<client> :<info>",
+    RPL_ADMINLOC2 = 258 => "<client> :<info>",
+    RPL_ADMINEMAIL = 259 => "<client> :<info>",
+    RPL_TRACELOG = 261 => "<client> File <logfile> <debug level>",
+    RPL_TRACEEND = 262 => "<client> <server name> <version> :End of TRACE",
+    RPL_TRYAGAIN = 263 => "<client> <command> :Please wait a while and try again.",
+    RPL_LOCALUSERS = 265 => "<client> [<u> <m>] :Current local users <u>, max <m>",
+    RPL_GLOBALUSERS = 266 => "<client> [<u> <m>] :Current global users <u>, max <m>",
+    RPL_WHOISCERTFP = 276 => "<client> <nick> :has client certificate fingerprint <fingerprint>",
+    RPL_NONE = 300 => "<client> :<anything>",
+    RPL_AWAY = 301 => "<client> <nick> :<message>",
+    RPL_USERHOST = 302 => "<client> :[<reply>{ <reply>}]",
+    RPL_ISON = 303 => "<client> :[<nickname>{ <nickname>}]",
+    RPL_UNAWAY = 305 => "<client> :You are no longer marked as being away",
+    RPL_NOWAWAY = 306 => "<client> :You have been marked as being away",
+    RPL_WHOISREGNICK = 307 => "<client> <nick> :has identified for this nick",
+    RPL_WHOISUSER = 311 => "<client> <nick> <username> <host> * :<realname>",
+    RPL_WHOISSERVER = 312 => "<client> <nick> <server> :<server info>",
+    RPL_WHOISOPERATOR = 313 => "<client> <nick> :is an IRC operator",
+    RPL_WHOWASUSER = 314 => "<client> <nick> <username> <host> * :<realname>",
+    RPL_ENDOFWHO = 315 => "<client> <mask> :End of WHO list",
+    RPL_WHOISCHANOP = 316 => "<client> <nick> :is a channel operator",
+    RPL_WHOISIDLE = 317 => "<client> <nick> <secs> <signon> :seconds idle, signon time",
+    RPL_ENDOFWHOIS = 318 => "<client> <nick> :End of /WHOIS list",
+    RPL_WHOISCHANNELS = 319 => "<client> <nick> :[prefix]<channel>{ [prefix]<channel>}",
+    RPL_WHOISSPECIAL = 320 => "<client> <nick> :<text>",
+    RPL_LISTSTART = 321 => "<client> Channel :Users  Name",
+    RPL_LIST = 322 => "<client> <channel> <client count> :<topic>",
+    RPL_LISTEND = 323 => "<client> :End of /LIST",
+    RPL_CHANNELMODEIS = 324 => "<client> <channel> <modestring> <mode arguments>...",
+    RPL_UNIQOPIS = 325 => "<client> <channel> <nickname>",
+    RPL_CREATIONTIME = 329 => "<client> <channel> <creationtime>",
+    RPL_WHOISACCOUNT = 330 => "<client> <nick> <account> :is logged in as",
+    RPL_NOTOPIC = 331 => "<client> <channel> :No topic is set",
+    RPL_TOPIC = 332 => "<client> <channel> :<topic>",
+    RPL_TOPICWHOTIME = 333 => "<client> <channel> <nick> <setat>",
+    RPL_WHOISBOT = 335 => "<client> <nick> :is a bot",
+    RPL_INVITELIST = 336 => "<client> <channel>",
+    RPL_ENDOFINVITELIST = 337 => "<client> :End of /INVITE list",
+    RPL_WHOISACTUALLY = 338 => "<client> <nick> [<host>] [<ip>] :is actually using host",
+    RPL_INVITING = 341 => "<client> <nick> <channel>",
+    RPL_SUMMONING = 342 => "<client> <user> :Summoning user to IRC",
+    RPL_INVEXLIST = 346 => "<client> <channel> <mask>",
+    RPL_ENDOFINVEXLIST = 347 => "<client> <channel> :End of Channel Invite Exception List",
+    RPL_EXCEPTLIST = 348 => "<client> <channel> <mask>",
+    RPL_ENDOFEXCEPTLIST = 349 => "<client> <channel> :End of channel exception list",
+    RPL_VERSION = 351 => "<client> <version> <server> :<comments>",
+    RPL_WHOREPLY = 352 => "<client> <channel> <username> <host> <server> <nick> <flags> :<hopcount> <realname>",
+    RPL_NAMREPLY = 353 => "<client> <symbol> <channel> :[prefix]<nick>{ [prefix]<nick>}",
+    RPL_WHOSPCRPL = 354 => "<client> [<token>] <fields>...",
+    RPL_KILLDONE = 361 => "<client> <nick> :<text>",
+    RPL_CLOSING = 362 => "<client> <server> :<text>",
+    RPL_CLOSEEND = 363 => "<client> <count> :<text>",
+    RPL_LINKS = 364 => "<client> * <server> :<hopcount> <server info>",
+    RPL_ENDOFLINKS = 365 => "<client> * :End of /LINKS list",
+    RPL_ENDOFNAMES = 366 => "<client> <channel> :End of /NAMES list",
+    RPL_BANLIST = 367 => "<client> <channel> <mask> [<who> <set-ts>]",
+    RPL_ENDOFBANLIST = 368 => "<client> <channel> :End of channel ban list",
+    RPL_ENDOFWHOWAS = 369 => "<client> <nick> :End of WHOWAS",
+    RPL_INFO = 371 => "<client> :<string>",
+    RPL_MOTD = 372 => "<client> :<line of the motd>",
+    RPL_INFOSTART = 373 => "<client> :<string>",
+    RPL_ENDOFINFO = 374 => "<client> :End of INFO list",
+    RPL_MOTDSTART = 375 => "<client> :- <server> Message of the day - ",
+    RPL_ENDOFMOTD = 376 => "<client> :End of /MOTD command.",
+    RPL_WHOISHOST = 378 => "<client> <nick> :is connecting from *@<host> <ip>",
+    RPL_WHOISMODES = 379 => "<client> <nick> :is using modes <modes>",
+    RPL_YOUREOPER = 381 => "<client> :You are now an IRC operator",
+    RPL_REHASHING = 382 => "<client> <config file> :Rehashing",
+    RPL_YOURESERVICE = 383 => "<client> :You are service <servicename>",
+    RPL_MYPORTIS = 384 => "<client> <port> :Port to local server is",
+    RPL_TIME = 391 => "<client> <server> [<timestamp> [<TS offset>]] :<human-readable time>",
+    RPL_USERSSTART = 392 => "<client> :UserID   Terminal  Host",
+    RPL_USERS = 393 => "<client> :<username> <ttyline> <hostname>",
+    RPL_ENDOFUSERS = 394 => "<client> :End of users",
+    RPL_NOUSERS = 395 => "<client> :Nobody logged in",
+    RPL_VISIBLEHOST = 396 => "<client> <hostname> :is now your displayed host",
+    ERR_UNKNOWNERROR = 400 => "<client> <command>{ <subcommand>} :<info>",
+    ERR_NOSUCHNICK = 401 => "<client> <nickname> :No such nick/channel",
+    ERR_NOSUCHSERVER = 402 => "<client> <server name> :No such server",
+    ERR_NOSUCHCHANNEL = 403 => "<client> <channel> :No such channel",
+    ERR_CANNOTSENDTOCHAN = 404 => "<client> <channel> :Cannot send to channel",
+    ERR_TOOMANYCHANNELS = 405 => "<client> <channel> :You have joined too many channels",
+    ERR_WASNOSUCHNICK = 406 => "<client> <nickname> :There was no such nickname",
+    ERR_TOOMANYTARGETS = 407 => "<client> <target> :<error code> recipients. <abort message>",
+    ERR_NOSUCHSERVICE = 408 => "<client> <service name> :No such service",
+    ERR_NOORIGIN = 409 => "<client> :No origin specified",
+    ERR_INVALIDCAPCMD = 410 => "<client> <subcommand> :Invalid CAP command",
+    ERR_NORECIPIENT = 411 => "<client> :No recipient given (<command>)",
+    ERR_NOTEXTTOSEND = 412 => "<client> :No text to send",
+    ERR_NOTOPLEVEL = 413 => "<client> <mask> :No toplevel domain specified",
+    ERR_WILDTOPLEVEL = 414 => "<client> <mask> :Wildcard in toplevel domain",
+    ERR_BADMASK = 415 => "<client> <mask> :Bad Server/host mask",
+    ERR_INPUTTOOLONG = 417 => "<client> :Input line was too long",
+    ERR_UNKNOWNCOMMAND = 421 => "<client> <command> :Unknown command",
+    ERR_NOMOTD = 422 => "<client> :MOTD File is missing",
+    ERR_NOADMININFO = 423 => "<client> <server> :No administrative info available",
+    ERR_FILEERROR = 424 => "<client> :File error doing <file op> on <file>",
+    ERR_NONICKNAMEGIVEN = 431 => "<client> :No nickname given",
+    ERR_ERRONEUSNICKNAME = 432 => "<client> <nick> :Erroneus nickname",
+    ERR_NICKNAMEINUSE = 433 => "<client> <nick> :Nickname is already in use",
+    ERR_NICKCOLLISION = 436 => "<client> <nick> :Nickname collision KILL from <user>@<host>",
+    ERR_UNAVAILRESOURCE = 437 => "<client> <nick/channel> :Nick/channel is temporarily unavailable",
+    ERR_SERVICESDOWN = 440 => "<client> :Services are currently unavailable",
+    ERR_USERNOTINCHANNEL = 441 => "<client> <nick> <channel> :They aren't on that channel",
+    ERR_NOTONCHANNEL = 442 => "<client> <channel> :You're not on that channel",
+    ERR_USERONCHANNEL = 443 => "<client> <nick> <channel> :is already on channel",
+    ERR_NOLOGIN = 444 => "<client> <user> :User not logged in",
+    ERR_SUMMONDISABLED = 445 => "<client> :SUMMON has been disabled",
+    ERR_USERSDISABLED = 446 => "<client> :USERS has been disabled",
+    ERR_NONICKCHANGE = 447 => "<client> :Cannot change nickname while on <channel>",
+    ERR_FORBIDDENCHANNEL = 448 => "<client> <channel> :Cannot join channel: <reason>",
+    ERR_NOTREGISTERED = 451 => "<client> :You have not registered",
+    ERR_NEEDMOREPARAMS = 461 => "<client> <command> :Not enough parameters",
+    ERR_ALREADYREGISTERED = 462 => "<client> :You may not reregister",
+    ERR_NOPERMFORHOST = 463 => "<client> :Your host isn't among the privileged",
+    ERR_PASSWDMISMATCH = 464 => "<client> :Password incorrect",
+    ERR_YOUREBANNEDCREEP = 465 => "<client> :You are banned from this server.",
+    ERR_YOUWILLBEBANNED = 466 => "<client>",
+    ERR_KEYSET = 467 => "<client> <channel> :Channel key already set",
+    ERR_CHANNELISFULL = 471 => "<client> <channel> :Cannot join channel (+l)",
+    ERR_UNKNOWNMODE = 472 => "<client> <modechar> :is unknown mode char to me",
+    ERR_INVITEONLYCHAN = 473 => "<client> <channel> :Cannot join channel (+i)",
+    ERR_BANNEDFROMCHAN = 474 => "<client> <channel> :Cannot join channel (+b)",
+    ERR_BADCHANNELKEY = 475 => "<client> <channel> :Cannot join channel (+k)",
+    ERR_BADCHANMASK = 476 => "<channel> :Bad Channel Mask",
+    ERR_NOCHANMODES = 477 => "<client> <channel> :Channel doesn't support modes",
+    ERR_BANLISTFULL = 478 => "<client> <channel> <char> :Channel list is full",
+    ERR_NOPRIVILEGES = 481 => "<client> :Permission Denied- You're not an IRC operator",
+    ERR_CHANOPRIVSNEEDED = 482 => "<client> <channel> :You're not channel operator",
+    ERR_CANTKILLSERVER = 483 => "<client> :You cant kill a server!",
+    ERR_RESTRICTED = 484 => "<client> :Your connection is restricted!",
+    ERR_UNIQOPPRIVSNEEDED = 485 => "<client> :You're not the original channel operator",
+    ERR_SECUREONLYCHAN = 489 => "<client> <channel> :Cannot join channel (+z)",
+    ERR_NOOPERHOST = 491 => "<client> :No O-lines for your host",
+    ERR_NOSERVICEHOST = 492 => "<client> :<text>",
+    ERR_UMODEUNKNOWNFLAG = 501 => "<client> :Unknown MODE flag",
+    ERR_USERSDONTMATCH = 502 => "<client> :Cant change mode for other users",
+    ERR_HELPNOTFOUND = 524 => "<client> <subject> :No help available on this topic",
+    ERR_INVALIDKEY = 525 => "<client> <target chan> :Key is not well-formed",
+    RPL_STARTTLS = 670 => "<client> :STARTTLS successful, proceed with TLS handshake",
+    RPL_WHOISSECURE = 671 => "<client> <nick> :is using a secure connection",
+    ERR_STARTTLS = 691 => "<client> :STARTTLS failed",
+    ERR_INVALIDMODEPARAM = 696 => "<client> <target chan/user> <mode char> <parameter> :<description>",
+    RPL_HELPSTART = 704 => "<client> <subject> :<first line of help section>",
+    RPL_HELPTXT = 705 => "<client> <subject> :<line of help text>",
+    RPL_ENDOFHELP = 706 => "<client> <subject> :<last line of help text>",
+    ERR_NOPRIVS = 723 => "<client> <priv> :Insufficient oper privileges.",
+    RPL_MONONLINE = 730 => "<client> :<target>[!<user>@<host>]{,<target>[!<user>@<host>]}",
+    RPL_MONOFFLINE = 731 => "<client> :<target>{,<target>}",
+    RPL_MONLIST = 732 => "<client> :<target>{,<target>}",
+    RPL_ENDOFMONLIST = 733 => "<client> :End of MONITOR list",
+    ERR_MONLISTFULL = 734 => "<client> <limit> <targets> :Monitor list is full.",
+    RPL_LOGGEDIN = 900 => "<client> <nick>!<user>@<host> <account> :You are now logged in as <username>",
+    RPL_LOGGEDOUT = 901 => "<client> <nick>!<user>@<host> :You are now logged out",
+    ERR_NICKLOCKED = 902 => "<client> :You must use a nick assigned to you",
+    RPL_SASLSUCCESS = 903 => "<client> :SASL authentication successful",
+    ERR_SASLFAIL = 904 => "<client> :SASL authentication failed",
+    ERR_SASLTOOLONG = 905 => "<client> :SASL message too long",
+    ERR_SASLABORTED = 906 => "<client> :SASL authentication aborted",
+    ERR_SASLALREADY = 907 => "<client> :You have already authenticated using SASL",
+    RPL_SASLMECHS = 908 => "<client> <mechanisms> :are available SASL mechanisms",
+}
+
+/// The code of a three digit numeric command, whether it's one we know
+/// or not
+pub fn numeric_code(command: &str) -> Option<u16> {
+    if command.len() == 3 && command.bytes().all(|b| b.is_ascii_digit()) {
+        command.parse().ok()
+    } else {
+        None
+    }
+}
+
+impl Response {
+    pub fn from_command(command: &str) -> Option<Response> {
+        numeric_code(command).and_then(Response::from_code)
+    }
+
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+
+    /// Whether this is one of the ERR_ replies
+    pub fn is_error(self) -> bool {
+        self.name().starts_with("ERR_")
+    }
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:03}", self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_code_test() {
+        assert_eq!(Some(Response::RPL_WELCOME), Response::from_code(1));
+        assert_eq!(Some(Response::ERR_NICKNAMEINUSE), Response::from_code(433));
+        assert_eq!(Some(Response::RPL_SASLMECHS), Response::from_code(908));
+        assert_eq!(Some(Response::RPL_WHOISCHANOP), Response::from_code(316));
+        assert_eq!(Some(Response::RPL_MYPORTIS), Response::from_code(384));
+        assert_eq!(None, Response::from_code(999));
+    }
+
+    #[test]
+    fn from_command_test() {
+        assert_eq!(Some(Response::RPL_ISUPPORT), Response::from_command("005"));
+        assert_eq!(Some(Response::RPL_NAMREPLY), Response::from_command("353"));
+        assert_eq!(None, Response::from_command("5"));
+        assert_eq!(None, Response::from_command("PRIVMSG"));
+    }
+
+    #[test]
+    fn display_test() {
+        assert_eq!("001", Response::RPL_WELCOME.to_string());
+        assert_eq!("RPL_WELCOME", Response::RPL_WELCOME.name());
+        assert_eq!("<client> <nick> :Nickname is already in use", Response::ERR_NICKNAMEINUSE.layout());
+        assert!(Response::ERR_SASLFAIL.is_error());
+        assert!(!Response::RPL_SASLSUCCESS.is_error());
+    }
+}
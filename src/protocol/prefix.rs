@@ -1,9 +1,13 @@
-use std::str;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::protocol::error::ParseError;
 
 /*
  * Helper to store, parse and encode IRC prefix
  */
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct Prefix {
     // nick or server
     pub nick: String,
@@ -12,40 +16,64 @@ pub struct Prefix {
     pub host: Option<String>
 }
 
-impl Prefix {
+impl FromStr for Prefix {
+    type Err = ParseError;
 
-    pub fn from_string(x: String) -> Prefix {
-        let mut i = x.chars().fuse();
+    fn from_str(x: &str) -> Result<Prefix, ParseError> {
+        if x.is_empty() || x.starts_with('!') || x.starts_with('@') {
+            return Err(ParseError::EmptySource { offset: 0 });
+        }
 
-        let nick = i.by_ref().take_while(|c| c != &'!').collect::<String>();
+        Ok(PrefixRef::parse(x).to_owned())
+    }
+}
 
-        let (lower, _) = i.size_hint();
+/// Borrowed counterpart of Prefix, sliced straight out of the line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrefixRef<'a> {
+    pub nick: &'a str,
+    pub user: Option<&'a str>,
+    pub host: Option<&'a str>
+}
 
-        let (user, host) = if lower > 0 {
-            let user = i.by_ref().take_while(|c| c != &'@').collect::<String>();
-            let host = i.by_ref().collect::<String>();
+impl<'a> PrefixRef<'a> {
 
-            (Some(user), Some(host))
-        } else {
-            (None, None)
-        };
+    pub fn parse(x: &'a str) -> PrefixRef<'a> {
+        match x.find('!') {
+            Some(bang) => {
+                let nick = &x[..bang];
+                let rest = &x[bang + 1..];
 
-        Prefix{nick: nick, user: user, host: host}
+                match rest.find('@') {
+                    Some(at) => PrefixRef{nick, user: Some(&rest[..at]), host: Some(&rest[at + 1..])},
+                    None => PrefixRef{nick, user: Some(rest), host: Some("")},
+                }
+            }
+            None => PrefixRef{nick: x, user: None, host: None},
+        }
     }
 
-    pub fn to_string(&self) -> String {
-        let mut s = String::new();
+    pub fn to_owned(&self) -> Prefix {
+        Prefix{
+            nick: self.nick.to_string(),
+            user: self.user.map(|u| u.to_string()),
+            host: self.host.map(|h| h.to_string()),
+        }
+    }
+}
 
-        s.push_str(self.nick.as_ref());
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.nick)?;
 
-        if !self.user.is_none() {
-            s.push_str(format!("!{}", self.user.as_ref().unwrap()).as_ref());
-            if !self.host.is_none() {
-                s.push_str(format!("@{}", self.host.as_ref().unwrap()).as_ref());
+        if let Some(user) = &self.user {
+            write!(f, "!{}", user)?;
+            if let Some(host) = &self.host {
+                write!(f, "@{}", host)?;
             }
         }
 
-        s
+        Ok(())
     }
 }
 
@@ -54,9 +82,9 @@ mod tests {
     use super::*;
 
     #[test]
-    fn prefix_only_server_from_string_test() {
+    fn prefix_only_server_from_str_test() {
         let sample: String = "tolkien.freenode.net".to_string();
-        let prefix = Prefix::from_string(sample.clone());
+        let prefix: Prefix = sample.parse().unwrap();
 
         assert_eq!(prefix.nick, sample);
         assert!(prefix.user.is_none());
@@ -65,9 +93,9 @@ mod tests {
     }
 
     #[test]
-    fn prefix_from_string_test() {
+    fn prefix_from_str_full_test() {
         let sample: String = "the_angry_angel!~karl@127.0.0.1".to_string();
-        let prefix: Prefix = Prefix::from_string(sample.clone());
+        let prefix: Prefix = sample.parse().unwrap();
     
         assert_eq!(prefix.nick, "the_angry_angel");
         assert_eq!(prefix.user.as_ref().unwrap(), "~karl");
@@ -75,4 +103,27 @@ mod tests {
 
         assert_eq!(prefix.to_string(), sample);
     }
+
+    #[test]
+    fn prefix_ref_parse_test() {
+        let sample = "the_angry_angel!~karl@127.0.0.1";
+        let prefix = PrefixRef::parse(sample);
+
+        assert_eq!(prefix.nick, "the_angry_angel");
+        assert_eq!(prefix.user, Some("~karl"));
+        assert_eq!(prefix.host, Some("127.0.0.1"));
+        assert_eq!(prefix.to_owned(), sample.parse().unwrap());
+
+        let server = PrefixRef::parse("tolkien.freenode.net");
+        assert_eq!(server.to_owned(), "tolkien.freenode.net".parse().unwrap());
+    }
+
+    #[test]
+    fn prefix_from_str_test() {
+        let prefix: Prefix = "dan!d@localhost".parse().unwrap();
+
+        assert_eq!(prefix.nick, "dan");
+        assert_eq!(Err(ParseError::EmptySource { offset: 0 }), "".parse::<Prefix>());
+        assert_eq!(Err(ParseError::EmptySource { offset: 0 }), "!d@localhost".parse::<Prefix>());
+    }
 }
@@ -0,0 +1,110 @@
+use crate::protocol::wire::RawMsg;
+
+/*
+ * Helper to keep credentials out of logs
+ */
+
+const REDACTED: &str = "<redacted>";
+
+// AUTHENTICATE params that are part of the exchange rather than credentials
+const SASL_CONTROL: &[&str] = &["+", "*", "PLAIN", "EXTERNAL", "SCRAM-SHA-1", "SCRAM-SHA-256"];
+
+fn is_identify(text: &str) -> bool {
+    text.split(' ')
+        .next()
+        .is_some_and(|word| word.eq_ignore_ascii_case("IDENTIFY") || word.eq_ignore_ascii_case("REGISTER"))
+}
+
+/// The message as it would be sent, with any passwords or SASL payloads
+/// replaced, for use in logs
+pub fn redacted(msg: &RawMsg) -> String {
+    let command = msg.command.to_ascii_uppercase();
+    let mut msg = msg.clone();
+
+    match command.as_ref() {
+        "PASS" => {
+            for param in msg.params.iter_mut() {
+                *param = REDACTED.to_string();
+            }
+        }
+        "OPER" => {
+            // OPER <name> <password>
+            for param in msg.params.iter_mut().skip(1) {
+                *param = REDACTED.to_string();
+            }
+        }
+        "AUTHENTICATE" => {
+            for param in msg.params.iter_mut() {
+                if !SASL_CONTROL.contains(&param.to_ascii_uppercase().as_ref()) {
+                    *param = REDACTED.to_string();
+                }
+            }
+        }
+        "PRIVMSG" if msg.params.len() == 2 => {
+            let target = msg.params[0].to_ascii_uppercase();
+
+            // NickServ, or NickServ@services.example.com on some networks
+            let nickserv = target == "NICKSERV" || target.starts_with("NICKSERV@");
+
+            if nickserv && is_identify(&msg.params[1]) {
+                let verb = msg.params[1].split(' ').next().unwrap_or("").to_string();
+                msg.params[1] = format!("{} {}", verb, REDACTED);
+            }
+        }
+        "NICKSERV" | "NS" if msg.params.first().is_some_and(|p| is_identify(p)) => {
+            for param in msg.params.iter_mut().skip(1) {
+                *param = REDACTED.to_string();
+            }
+
+            // IDENTIFY and the password may have been sent as one param
+            if msg.params.len() == 1 && msg.params[0].contains(' ') {
+                let verb = msg.params[0].split(' ').next().unwrap_or("").to_string();
+                msg.params[0] = format!("{} {}", verb, REDACTED);
+            }
+        }
+        _ => {}
+    }
+
+    msg.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redact(line: &str) -> String {
+        redacted(&line.parse().unwrap())
+    }
+
+    #[test]
+    fn pass_test() {
+        assert_eq!("PASS <redacted>", redact("PASS hunter2"));
+    }
+
+    #[test]
+    fn oper_test() {
+        assert_eq!("OPER karl <redacted>", redact("OPER karl hunter2"));
+    }
+
+    #[test]
+    fn authenticate_test() {
+        assert_eq!("AUTHENTICATE PLAIN", redact("AUTHENTICATE PLAIN"));
+        assert_eq!("AUTHENTICATE +", redact("AUTHENTICATE +"));
+        assert_eq!("AUTHENTICATE <redacted>", redact("AUTHENTICATE a2FybABrYXJsAGh1bnRlcjI="));
+    }
+
+    #[test]
+    fn nickserv_test() {
+        assert_eq!("PRIVMSG NickServ :IDENTIFY <redacted>", redact("PRIVMSG NickServ :IDENTIFY karl hunter2"));
+        assert_eq!("PRIVMSG NickServ :identify <redacted>", redact("PRIVMSG NickServ :identify hunter2"));
+        assert_eq!("PRIVMSG NickServ@services.example.com :IDENTIFY <redacted>", redact("PRIVMSG NickServ@services.example.com :IDENTIFY hunter2"));
+        assert_eq!("NS IDENTIFY <redacted>", redact("NS IDENTIFY hunter2"));
+        assert_eq!("NICKSERV :IDENTIFY <redacted>", redact("NICKSERV :IDENTIFY hunter2"));
+    }
+
+    #[test]
+    fn untouched_test() {
+        assert_eq!("PRIVMSG #chan :IDENTIFY hunter2", redact("PRIVMSG #chan :IDENTIFY hunter2"));
+        assert_eq!("PRIVMSG NickServ :INFO karl", redact("PRIVMSG NickServ :INFO karl"));
+    }
+}
@@ -0,0 +1,131 @@
+use crate::protocol::codec::MAX_BODY_LENGTH;
+use crate::protocol::wire::RawMsg;
+
+/*
+ * Helper to break long PRIVMSG/NOTICE text into lines the server will relay
+ * without truncating
+ */
+
+/// A `nick!user@host` prefix as long as most networks allow, for when our
+/// own prefix isn't known yet
+pub const DEFAULT_PREFIX_LENGTH: usize = 30 + 1 + 10 + 1 + 63;
+
+// the largest char boundary at or below max
+fn floor_char_boundary(text: &str, max: usize) -> usize {
+    if max >= text.len() {
+        return text.len();
+    }
+
+    (0..=max).rev().find(|i| text.is_char_boundary(*i)).unwrap_or(0)
+}
+
+/// Splits `text` into as many `command` messages to `target` as it takes
+/// for each to fit in 512 bytes once the server has prepended our
+/// `:nick!user@host ` prefix, `prefix_length` being the length of that
+/// prefix without the colon and space.
+///
+/// Lines are broken on the last space that fits, or on the last UTF-8 char
+/// boundary if there's no space. Any CR or LF in `text` also starts a new
+/// message, as they can't be sent.
+pub fn split_message(command: &str, target: &str, text: &str, prefix_length: usize) -> Vec<RawMsg> {
+    // ":" prefix " " command " " target " :" text CR LF
+    let overhead = 1 + prefix_length + 1 + command.len() + 1 + target.len() + 2 + 2;
+    let budget = MAX_BODY_LENGTH.saturating_sub(overhead);
+
+    let mut messages = Vec::new();
+
+    for line in text.split(['\r', '\n']).filter(|l| !l.is_empty()) {
+        let mut rest = line;
+
+        while !rest.is_empty() {
+            let chunk = if rest.len() <= budget {
+                rest
+            } else {
+                let cut = floor_char_boundary(rest, budget);
+
+                // a space right at the cut still leaves rest[..cut] to fit
+                match rest.as_bytes()[..=cut].iter().rposition(|b| *b == b' ') {
+                    Some(space) if space > 0 => &rest[..space],
+                    // always make progress, even when the budget is tiny
+                    _ if cut == 0 => {
+                        let first = rest.chars().next().map_or(rest.len(), |c| c.len_utf8());
+                        &rest[..first]
+                    }
+                    _ => &rest[..cut],
+                }
+            };
+
+            messages.push(RawMsg::new(
+                command.to_string(),
+                Some(vec![target.to_string(), chunk.to_string()]),
+            ));
+
+            rest = &rest[chunk.len()..];
+            // the space we broke on belongs to neither line
+            rest = rest.strip_prefix(' ').unwrap_or(rest);
+        }
+    }
+
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(messages: &[RawMsg]) -> Vec<&str> {
+        messages.iter().map(|m| m.params[1].as_ref()).collect()
+    }
+
+    #[test]
+    fn short_message_test() {
+        let messages = split_message("PRIVMSG", "#chan", "Hello world!", DEFAULT_PREFIX_LENGTH);
+
+        assert_eq!(vec!["Hello world!"], texts(&messages));
+    }
+
+    #[test]
+    fn word_boundary_test() {
+        let text = "word ".repeat(200);
+        let prefix = "dan!d@localhost";
+        let messages = split_message("PRIVMSG", "#chan", text.trim_end(), prefix.len());
+
+        assert!(messages.len() > 1);
+
+        for msg in &messages {
+            let relayed = format!(":{} {}\r\n", prefix, msg);
+            assert!(relayed.len() <= MAX_BODY_LENGTH);
+            assert!(msg.params[1].split(' ').all(|w| w == "word"));
+        }
+
+        assert_eq!(text.trim_end(), texts(&messages).join(" "));
+    }
+
+    #[test]
+    fn space_at_budget_test() {
+        // ":" prefix " PRIVMSG #chan :" text CR LF leaves 388 bytes of text
+        let budget = MAX_BODY_LENGTH - (DEFAULT_PREFIX_LENGTH + 19);
+        let first = format!("x {}", "a".repeat(budget - 2));
+        let text = format!("{} {}", first, "b".repeat(10));
+
+        let messages = split_message("PRIVMSG", "#chan", &text, DEFAULT_PREFIX_LENGTH);
+
+        assert_eq!(vec![first.as_str(), "bbbbbbbbbb"], texts(&messages));
+    }
+
+    #[test]
+    fn utf8_boundary_test() {
+        let text = "é".repeat(600);
+        let messages = split_message("NOTICE", "dan", &text, DEFAULT_PREFIX_LENGTH);
+
+        assert!(messages.len() > 1);
+        assert_eq!(text, texts(&messages).concat());
+    }
+
+    #[test]
+    fn newlines_test() {
+        let messages = split_message("PRIVMSG", "#chan", "one\r\ntwo\n\nthree", DEFAULT_PREFIX_LENGTH);
+
+        assert_eq!(vec!["one", "two", "three"], texts(&messages));
+    }
+}
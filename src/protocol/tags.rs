@@ -1,69 +1,236 @@
+use std::borrow::Cow;
 use std::collections::BTreeMap;
-use std::str;
-use std::iter::Iterator;
+use std::str::FromStr;
+
+use crate::protocol::error::ParseError;
 
 /*
  * Helper to store, parse and encode IRCv3 tags
  */
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum TagValue {
     String(String),
     True
 }
 
+/// A tag key split into its parts, so `+example.com/foo` can be told apart
+/// from a plain `foo` sent by the server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TagKey<'a> {
+    /// Client-only tags start with `+` and are relayed by the server untouched
+    pub client_only: bool,
+    pub vendor: Option<&'a str>,
+    pub name: &'a str,
+}
+
+impl<'a> TagKey<'a> {
+
+    pub fn parse(key: &'a str) -> TagKey<'a> {
+        let (client_only, key) = match key.strip_prefix('+') {
+            Some(key) => (true, key),
+            None => (false, key),
+        };
+
+        match key.rfind('/') {
+            Some(i) => TagKey{client_only, vendor: Some(&key[..i]), name: &key[i + 1..]},
+            None => TagKey{client_only, vendor: None, name: key},
+        }
+    }
+}
+
+/// Escapes a tag value for the wire, as per the IRCv3 message-tags spec
+pub fn escape(value: &str) -> Cow<'_, str> {
+    if !value.contains([';', ' ', '\\', '\r', '\n']) {
+        return Cow::Borrowed(value);
+    }
+
+    let mut escaped = String::with_capacity(value.len() + 8);
+    for c in value.chars() {
+        match c {
+            ';' => escaped.push_str("\\:"),
+            ' ' => escaped.push_str("\\s"),
+            '\\' => escaped.push_str("\\\\"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+
+    Cow::Owned(escaped)
+}
+
+/// Reverses `escape`. Unknown escapes drop the backslash, as does a lone
+/// backslash at the end of the value.
+pub fn unescape(value: &str) -> Cow<'_, str> {
+    if !value.contains('\\') {
+        return Cow::Borrowed(value);
+    }
+
+    let mut unescaped = String::with_capacity(value.len());
+    let mut i = value.chars();
+    while let Some(c) = i.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match i.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => {}
+        }
+    }
+
+    Cow::Owned(unescaped)
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct Tags {
     collection: BTreeMap<String, TagValue>,
 }
 
 impl Tags {
 
-    pub fn from_string(x: String) -> Tags {
+    pub fn new() -> Tags {
+        Tags::default()
+    }
+
+    // only for raw tags that have already been checked, see FromStr
+    fn from_string(x: String) -> Tags {
         let collection: BTreeMap<String, TagValue> = x.split(';')
-            .map(|kv| 
-                kv.split('=').collect::<Vec<&str>>()
-            )
-            .map(|vec| {
-                if vec.len() == 2 {
-                    (vec[0].to_string(), TagValue::String(vec[1].to_string()))
-                } else {
-                    (vec[0].to_string(), TagValue::True)
-                }
+            .map(|kv| {
+                let mut i = kv.splitn(2, '=');
+                (i.next().unwrap_or(""), i.next())
+            })
+            // an empty value is the same as no value at all
+            .map(|(k, v)| match v.filter(|v| !v.is_empty()) {
+                Some(v) => (k.to_string(), TagValue::String(unescape(v).into_owned())),
+                None => (k.to_string(), TagValue::True),
             })
             .collect();
 
-        Tags{collection: collection}
+        Tags{collection}
     }
 
     pub fn to_string(&self) -> Option<String> {
-        if self.collection.len() <= 0 {
+        if self.collection.is_empty() {
             return None
         }
 
         Some(
             self.collection.iter().map(|(k, v)|
                 match v {
-                    TagValue::String(s) => format!("{}={}", k, s),
-                    TagValue::True => format!("{}", k)
+                    TagValue::String(s) => format!("{}={}", k, escape(s)),
+                    TagValue::True => k.to_string()
                 }
             )
             .collect::<Vec<String>>()
             .join(";")
-            .to_string()
         )
     }
 
     pub fn get(&self, key: String) -> Option<&TagValue> {
-        return self.collection.get(&key);
+        self.collection.get(&key)
+    }
+
+    /// An empty string is stored as TagValue::True, the two being the same
+    /// on the wire
+    pub fn insert(&mut self, key: String, value: TagValue) -> Option<TagValue> {
+        let value = match value {
+            TagValue::String(s) if s.is_empty() => TagValue::True,
+            value => value,
+        };
+
+        self.collection.insert(key, value)
     }
 
     pub fn iter(&self) -> std::collections::btree_map::Iter<'_, String, TagValue> {
-        return self.collection.iter();
+        self.collection.iter()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = TagKey<'_>> {
+        self.collection.keys().map(|k| TagKey::parse(k))
+    }
+}
+
+impl FromStr for Tags {
+    type Err = ParseError;
+
+    fn from_str(x: &str) -> Result<Tags, ParseError> {
+        TagsRef::parse(x).map(|t| t.to_owned())
+    }
+}
+
+/// Borrowed view over the raw tag section of a line, without the leading @
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TagsRef<'a> {
+    raw: &'a str,
+}
+
+// keys are an optional client-only +, an optional vendor/ and then the name
+fn valid_key(key: &str) -> bool {
+    let key = key.strip_prefix('+').unwrap_or(key);
+
+    let (vendor, name) = match key.rfind('/') {
+        Some(i) => (Some(&key[..i]), &key[i + 1..]),
+        None => (None, key),
+    };
+
+    let vendor_ok = vendor.map_or(true, |v| {
+        !v.is_empty() && v.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'-')
+    });
+
+    vendor_ok && !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+impl<'a> TagsRef<'a> {
+
+    pub fn parse(raw: &'a str) -> Result<TagsRef<'a>, ParseError> {
+        let mut offset = 0;
+
+        for kv in raw.split(';') {
+            let key = kv.split('=').next().unwrap_or("");
+
+            if !valid_key(key) {
+                return Err(ParseError::InvalidTagKey { offset });
+            }
+
+            offset += kv.len() + 1;
+        }
+
+        Ok(TagsRef{raw})
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.raw
+    }
+
+    /// Key and raw value pairs, a value of None being a tag with no `=` or
+    /// an empty one. Values are still escaped, see `unescape`.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, Option<&'a str>)> {
+        self.raw.split(';').map(|kv| {
+            let mut i = kv.splitn(2, '=');
+            (i.next().unwrap_or(""), i.next().filter(|v| !v.is_empty()))
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<Option<&'a str>> {
+        self.iter().filter(|(k, _)| *k == key).last().map(|(_, v)| v)
+    }
+
+    pub fn to_owned(&self) -> Tags {
+        Tags::from_string(self.raw.to_string())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn from_string_test() {
@@ -79,10 +246,7 @@ mod tests {
         }
         */
 
-        assert!(match tags.get("rose".to_string()).unwrap() {
-            TagValue::True => true,
-            _ => false
-        });
+        assert!(matches!(tags.get("rose".to_string()).unwrap(), TagValue::True));
 
         assert!(match tags.get("id".to_string()).unwrap() {
             TagValue::String(s) => s == "123123",
@@ -91,4 +255,97 @@ mod tests {
 
         assert!(tags.to_string().unwrap() == sample);
     }
+
+    #[test]
+    fn tags_ref_test() {
+        let tags = TagsRef::parse("id=123123;rose").unwrap();
+
+        assert_eq!(Some(Some("123123")), tags.get("id"));
+        assert_eq!(Some(None), tags.get("rose"));
+        assert_eq!(None, tags.get("lily"));
+        assert_eq!(tags.to_owned(), Tags::from_string("id=123123;rose".to_string()));
+    }
+
+    #[test]
+    fn empty_value_test() {
+        let empty: Tags = "a=;b".parse().unwrap();
+        let missing: Tags = "a;b".parse().unwrap();
+
+        assert_eq!(missing, empty);
+        assert_eq!(Some(&TagValue::True), empty.get("a".to_string()));
+        assert_eq!("a;b", empty.to_string().unwrap());
+        assert_eq!(Some(None), TagsRef::parse("a=;b").unwrap().get("a"));
+
+        let mut inserted = Tags::new();
+        inserted.insert("a".to_string(), TagValue::String(String::new()));
+        assert_eq!(Some(&TagValue::True), inserted.get("a".to_string()));
+    }
+
+    #[test]
+    fn from_str_test() {
+        let tags: Tags = "+example.com/foo=bar;msgid=1".parse().unwrap();
+
+        assert_eq!("+example.com/foo=bar;msgid=1", tags.to_string().unwrap());
+    }
+
+    #[test]
+    fn invalid_key_test() {
+        assert_eq!(Err(ParseError::InvalidTagKey { offset: 0 }), "".parse::<Tags>());
+        assert_eq!(Err(ParseError::InvalidTagKey { offset: 7 }), "id=123;;rose".parse::<Tags>());
+        assert_eq!(Err(ParseError::InvalidTagKey { offset: 0 }), "/foo=bar".parse::<Tags>());
+        assert_eq!(Err(ParseError::InvalidTagKey { offset: 0 }), "fo o".parse::<Tags>());
+    }
+
+    #[test]
+    fn unescape_test() {
+        let tags = Tags::from_string(r"a=hello\sworld\:\\\r\n;b=\x\;c=x=y".to_string());
+
+        assert_eq!(Some(&TagValue::String("hello world;\\\r\n".to_string())), tags.get("a".to_string()));
+        assert_eq!(Some(&TagValue::String("x".to_string())), tags.get("b".to_string()));
+        assert_eq!(Some(&TagValue::String("x=y".to_string())), tags.get("c".to_string()));
+    }
+
+    #[test]
+    fn escape_test() {
+        let mut tags = Tags::new();
+        tags.insert("a".to_string(), TagValue::String("hello world;\\\r\n".to_string()));
+
+        assert_eq!(r"a=hello\sworld\:\\\r\n", tags.to_string().unwrap());
+    }
+
+    #[test]
+    fn tag_key_test() {
+        let tags = Tags::from_string("+example.com/foo=1;+draft/reply=2;msgid=3".to_string());
+        let keys: Vec<TagKey> = tags.keys().collect();
+
+        assert!(keys.contains(&TagKey { client_only: true, vendor: Some("example.com"), name: "foo" }));
+        assert!(keys.contains(&TagKey { client_only: true, vendor: Some("draft"), name: "reply" }));
+        assert!(keys.contains(&TagKey { client_only: false, vendor: None, name: "msgid" }));
+    }
+
+    proptest! {
+        #[test]
+        fn escape_round_trip(chars in proptest::collection::vec(any::<char>(), 0..64)) {
+            let value: String = chars.into_iter().collect();
+            prop_assert_eq!(value.clone(), unescape(&escape(&value)).into_owned());
+        }
+
+        #[test]
+        fn tags_round_trip(
+            collection in proptest::collection::btree_map(
+                "\\+?([a-z0-9.-]{1,8}/)?[a-zA-Z0-9-]{1,8}",
+                proptest::option::of("[^\\x00]*"),
+                1..5
+            )
+        ) {
+            let mut tags = Tags::new();
+            for (k, v) in collection {
+                let value = v.map_or(TagValue::True, TagValue::String);
+                tags.insert(k, value);
+            }
+
+            let line = tags.to_string().unwrap();
+            prop_assert_eq!(&tags, &line.parse::<Tags>().unwrap());
+        }
+    }
 }
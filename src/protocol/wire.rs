@@ -1,6 +1,12 @@
-use crate::protocol::tags::{Tags, TagValue};
-use crate::protocol::prefix::Prefix;
+use std::fmt;
+use std::str::FromStr;
 
+use crate::protocol::error::{ParamError, ParseError};
+use crate::protocol::numeric::Response;
+use crate::protocol::tags::{Tags, TagsRef};
+use crate::protocol::prefix::{Prefix, PrefixRef};
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct RawMsg {
     pub tags: Option<Tags>,
     pub source: Option<Prefix>,
@@ -10,108 +16,196 @@ pub struct RawMsg {
 
 impl RawMsg {
     pub fn new(command: String, optional_params: Option<Vec<String>>) -> RawMsg {
-        let params = if optional_params.is_none() {
-            vec![]
-        } else {
-            optional_params.unwrap()
-        };
+        let params = optional_params.unwrap_or_default();
 
         RawMsg {
             tags: None,
             source: None,
-            command: command,
-            params: params
+            command,
+            params
         }
     }
 
-    pub fn from_string(x: String) -> RawMsg {
-        let mut i = x.chars().fuse().peekable();
+    /// Checks the params can be written out and read back unchanged: only
+    /// the last may be empty, contain spaces or start with a colon.
+    pub fn validate(&self) -> Result<(), ParamError> {
+        if self.params.len() > MAX_PARAMS {
+            return Err(ParamError::TooMany { count: self.params.len() });
+        }
+
+        let middle = self.params.len().saturating_sub(1);
+
+        for (index, param) in self.params[..middle].iter().enumerate() {
+            if param.is_empty() {
+                return Err(ParamError::Empty { index });
+            }
+
+            if param.contains(' ') {
+                return Err(ParamError::ContainsSpace { index });
+            }
+
+            if param.starts_with(':') {
+                return Err(ParamError::LeadingColon { index });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The numeric reply this message carries, if it's a known one
+    pub fn response(&self) -> Option<Response> {
+        Response::from_command(&self.command)
+    }
+}
+
+/// Borrowed message parsed without copying, every field is a slice of the
+/// original line. Use `to_owned` to keep it past the lifetime of the line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawMsgRef<'a> {
+    pub tags: Option<TagsRef<'a>>,
+    pub source: Option<PrefixRef<'a>>,
+    pub command: &'a str,
+    pub params: Vec<&'a str>,
+}
+
+/// The most params a single message may carry
+pub const MAX_PARAMS: usize = 15;
+
+// splits off the next space delimited word, skipping any run of spaces after it
+fn next_word(x: &str) -> (&str, &str) {
+    match x.find(' ') {
+        Some(i) => (&x[..i], x[i..].trim_start_matches(' ')),
+        None => (x, ""),
+    }
+}
 
-        let tags: Option<Tags> = if i.peek() == Some(&'@') {
-            let tags_string = i.by_ref().skip(1).take_while(|c| c != &' ').collect::<String>();
+impl<'a> RawMsgRef<'a> {
 
-            Some(
-                Tags::from_string(tags_string)
-            )
+    pub fn parse(line: &'a str) -> Result<RawMsgRef<'a>, ParseError> {
+        if let Some(offset) = line.find('\0') {
+            return Err(ParseError::NulByte { offset });
+        }
+
+        if line.trim_matches(' ').is_empty() {
+            return Err(ParseError::EmptyLine { offset: 0 });
+        }
+
+        let offset_of = |rest: &str| line.len() - rest.len();
+        let mut rest = line;
+
+        let tags = if let Some(r) = rest.strip_prefix('@') {
+            let (tags, r) = next_word(r);
+            let tags = TagsRef::parse(tags).map_err(|e| e.shift(1))?;
+            rest = r;
+            Some(tags)
         } else {
             None
         };
 
-        let source: Option<Prefix> = if i.peek() == Some(&':') {
-            let prefix = i.by_ref()
-                .skip(1)
-                .take_while(|c| c != &' ')
-                .collect::<String>();
-            Some(
-                Prefix::from_string(prefix)
-            )
+        let source = if let Some(r) = rest.strip_prefix(':') {
+            let (source, r) = next_word(r);
+            if source.is_empty() {
+                return Err(ParseError::EmptySource { offset: offset_of(rest) });
+            }
+            rest = r;
+            Some(PrefixRef::parse(source))
         } else {
             None
         };
 
-        let command: String = i.by_ref().take_while(|c| c != &' ').collect::<String>();
+        let (command, r) = next_word(rest);
+        if command.is_empty() {
+            return Err(ParseError::MissingCommand { offset: offset_of(rest) });
+        }
+
+        let numeric = command.len() == 3 && command.bytes().all(|b| b.is_ascii_digit());
+        if !numeric && !command.bytes().all(|b| b.is_ascii_alphabetic()) {
+            return Err(ParseError::InvalidCommand { offset: offset_of(rest) });
+        }
+        rest = r;
+
+        let mut params = Vec::new();
+        while !rest.is_empty() {
+            if params.len() == MAX_PARAMS {
+                return Err(ParseError::TooManyParams { offset: offset_of(rest) });
+            }
 
-        let mut params: Vec<String> = i.by_ref()
-            .take_while(|c| c != &':')
-            .collect::<String>()
-            .trim()
-            .split(' ')
-            .map(|vec| vec.to_string())
-            .collect::<Vec<String>>();
+            if let Some(trailing) = rest.strip_prefix(':') {
+                params.push(trailing);
+                break;
+            }
 
-        let (lower, _) = i.size_hint();
-        if lower > 0 {
-            params.push(i.collect::<String>());
+            let (param, r) = next_word(rest);
+            params.push(param);
+            rest = r;
         }
 
-        RawMsg{tags: tags, source: source, command: command, params: params}
+        Ok(RawMsgRef{tags, source, command, params})
+    }
+
+    pub fn to_owned(&self) -> RawMsg {
+        RawMsg{
+            tags: self.tags.map(|t| t.to_owned()),
+            source: self.source.map(|s| s.to_owned()),
+            command: self.command.to_string(),
+            params: self.params.iter().map(|p| p.to_string()).collect(),
+        }
     }
+}
 
-    pub fn to_string(&self) -> String {
-        let mut s = String::new();
+impl FromStr for RawMsg {
+    type Err = ParseError;
 
-        if !self.tags.is_none() && !self.tags.is_none() {
-            s.push_str(format!("@{} ", self.tags.as_ref().unwrap().to_string().unwrap()).as_ref());
+    fn from_str(x: &str) -> Result<RawMsg, ParseError> {
+        RawMsgRef::parse(x).map(|m| m.to_owned())
+    }
+}
+
+impl fmt::Display for RawMsg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(tags) = self.tags.as_ref().and_then(|t| t.to_string()) {
+            write!(f, "@{} ", tags)?;
         }
-        
-        if !self.source.is_none() {
-            s.push_str(format!(":{} ", self.source.as_ref().unwrap().to_string()).as_ref());
+
+        if let Some(source) = &self.source {
+            write!(f, ":{} ", source)?;
         }
 
-        s.push_str(&self.command);
-        
-        for param in &self.params {
-            if param.contains(" ") {
-                s.push_str(" :");
-            } else {
-                s.push_str(" ");
+        write!(f, "{}", self.command)?;
+
+        if let Some((last, middle)) = self.params.split_last() {
+            for param in middle {
+                write!(f, " {}", param)?;
             }
 
-            s.push_str(&param);
+            if last.is_empty() || last.contains(' ') || last.starts_with(':') {
+                write!(f, " :{}", last)?;
+            } else {
+                write!(f, " {}", last)?;
+            }
         }
 
-        s
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::tags::TagValue;
+    use proptest::prelude::*;
 
     #[test]
-    fn from_string_complete_test() {
+    fn from_str_complete_test() {
         let sample = String::from("@id=234AB;rose :dan!d@localhost PRIVMSG #chan :Hey what's up!");
 
-        let msg = RawMsg::from_string(sample);
+        let msg = sample.parse::<RawMsg>().unwrap();
         let tags = msg.tags.unwrap();
         let source = msg.source.unwrap();
         let command = msg.command;
         let params = msg.params;
 
-        assert!(match tags.get("rose".to_string()).unwrap() {
-            TagValue::True => true,
-            _ => false
-        });
+        assert!(matches!(tags.get("rose".to_string()).unwrap(), TagValue::True));
 
         assert!(match tags.get("id".to_string()).unwrap() {
             TagValue::String(s) => s == "234AB",
@@ -125,10 +219,10 @@ mod tests {
     }
 
     #[test]
-    fn from_string_no_tags_test() {
+    fn from_str_no_tags_test() {
         let sample = String::from(":irc.example.com CAP LS * :multi-prefix extended-join sasl");
 
-        let msg = RawMsg::from_string(sample);
+        let msg = sample.parse::<RawMsg>().unwrap();
         let source = msg.source.unwrap();
 
         assert!(msg.tags.is_none());
@@ -141,10 +235,10 @@ mod tests {
     }
 
     #[test]
-    fn from_string_no_tags_no_source_test() {
+    fn from_str_no_tags_no_source_test() {
         let sample = String::from("CAP LS * :multi-prefix extended-join sasl");
 
-        let msg = RawMsg::from_string(sample);
+        let msg = sample.parse::<RawMsg>().unwrap();
 
         assert!(msg.tags.is_none());
         assert!(msg.source.is_none());
@@ -156,10 +250,10 @@ mod tests {
     }
 
     #[test]
-    fn from_string_no_tags_no_trailing_test() {
+    fn from_str_no_tags_no_trailing_test() {
         let sample = String::from(":dan!d@localhost PRIVMSG #chan Hey!");
 
-        let msg = RawMsg::from_string(sample);
+        let msg = sample.parse::<RawMsg>().unwrap();
 
         assert!(msg.tags.is_none());
         assert_eq!("dan!d@localhost", msg.source.unwrap().to_string());
@@ -169,6 +263,142 @@ mod tests {
         assert_eq!(2, msg.params.len());
     }
 
+    #[test]
+    fn response_test() {
+        let sample = String::from(":irc.example.com 433 * MrBotMcBotFace :Nickname is already in use");
+
+        let msg = sample.parse::<RawMsg>().unwrap();
+
+        assert_eq!(Some(Response::ERR_NICKNAMEINUSE), msg.response());
+        assert_eq!(None, RawMsg::new("PRIVMSG".to_string(), None).response());
+    }
+
+    #[test]
+    fn parse_ref_complete_test() {
+        let sample = "@id=234AB;rose :dan!d@localhost PRIVMSG #chan :Hey what's up!";
+
+        let msg = RawMsgRef::parse(sample).unwrap();
+
+        assert_eq!(Some("234AB"), msg.tags.unwrap().get("id").unwrap());
+        assert_eq!("dan", msg.source.unwrap().nick);
+        assert_eq!("PRIVMSG", msg.command);
+        assert_eq!(vec!["#chan", "Hey what's up!"], msg.params);
+        assert_eq!(sample.parse::<RawMsg>().unwrap(), msg.to_owned());
+    }
+
+    #[test]
+    fn parse_ref_matches_from_str_test() {
+        let samples = [
+            ":irc.example.com CAP LS * :multi-prefix extended-join sasl",
+            "CAP LS * :multi-prefix extended-join sasl",
+            ":dan!d@localhost PRIVMSG #chan Hey!",
+            "PING :irc.example.com",
+            "QUIT",
+        ];
+
+        for sample in samples.iter() {
+            assert_eq!(sample.parse::<RawMsg>().unwrap(), RawMsgRef::parse(sample).unwrap().to_owned());
+        }
+    }
+
+    #[test]
+    fn parse_ref_colon_in_middle_param_test() {
+        let msg = RawMsgRef::parse(":irc.example.com 333 dan #chan dan!d@host:1 1585581325").unwrap();
+
+        assert_eq!(vec!["dan", "#chan", "dan!d@host:1", "1585581325"], msg.params);
+    }
+
+    #[test]
+    fn from_str_test() {
+        let msg: RawMsg = "@id=234AB :dan!d@localhost PRIVMSG #chan :Hey what's up!".parse().unwrap();
+
+        assert_eq!("PRIVMSG", msg.command);
+        assert_eq!(vec!["#chan", "Hey what's up!"], msg.params);
+    }
+
+    #[test]
+    fn parse_errors_test() {
+        assert_eq!(Err(ParseError::EmptyLine { offset: 0 }), "".parse::<RawMsg>());
+        assert_eq!(Err(ParseError::EmptyLine { offset: 0 }), "   ".parse::<RawMsg>());
+        assert_eq!(Err(ParseError::NulByte { offset: 13 }), "PRIVMSG #chan\0 :hi".parse::<RawMsg>());
+        assert_eq!(Err(ParseError::MissingCommand { offset: 9 }), "@id=234AB".parse::<RawMsg>());
+        assert_eq!(Err(ParseError::MissingCommand { offset: 26 }), "@id=234AB :dan!d@localhost".parse::<RawMsg>());
+        assert_eq!(Err(ParseError::EmptySource { offset: 0 }), ": PRIVMSG #chan :hi".parse::<RawMsg>());
+        assert_eq!(Err(ParseError::InvalidCommand { offset: 0 }), "PRIV_MSG #chan :hi".parse::<RawMsg>());
+        assert_eq!(Err(ParseError::InvalidCommand { offset: 0 }), "0001 dan :hi".parse::<RawMsg>());
+        assert_eq!(Err(ParseError::InvalidTagKey { offset: 8 }), "@id=234;;rose PING :x".parse::<RawMsg>());
+
+        let too_many = format!("MODE #chan {}", vec!["a"; 15].join(" "));
+        assert_eq!(Err(ParseError::TooManyParams { offset: 39 }), too_many.parse::<RawMsg>());
+    }
+
+    #[test]
+    fn to_string_trailing_test() {
+        let params = |p: &[&str]| Some(p.iter().map(|x| x.to_string()).collect());
+
+        assert_eq!("TOPIC #chan :", RawMsg::new("TOPIC".to_string(), params(&["#chan", ""])).to_string());
+        assert_eq!("PRIVMSG #chan ::)", RawMsg::new("PRIVMSG".to_string(), params(&["#chan", ":)"])).to_string());
+        assert_eq!("PRIVMSG #chan hi", RawMsg::new("PRIVMSG".to_string(), params(&["#chan", "hi"])).to_string());
+        assert_eq!("QUIT", RawMsg::new("QUIT".to_string(), None).to_string());
+    }
+
+    #[test]
+    fn validate_test() {
+        let params = |p: &[&str]| Some(p.iter().map(|x| x.to_string()).collect());
+
+        assert_eq!(Ok(()), RawMsg::new("PRIVMSG".to_string(), params(&["#chan", ""])).validate());
+        assert_eq!(Err(ParamError::Empty { index: 0 }), RawMsg::new("PRIVMSG".to_string(), params(&["", "hi"])).validate());
+        assert_eq!(Err(ParamError::ContainsSpace { index: 0 }), RawMsg::new("PRIVMSG".to_string(), params(&["#a b", "hi"])).validate());
+        assert_eq!(Err(ParamError::LeadingColon { index: 1 }), RawMsg::new("MODE".to_string(), params(&["#chan", ":o", "dan"])).validate());
+        assert_eq!(Err(ParamError::TooMany { count: 16 }), RawMsg::new("MODE".to_string(), params(&["a"; 16])).validate());
+    }
+
+    fn valid_msg() -> impl Strategy<Value = RawMsg> {
+        let tags = proptest::option::of(proptest::collection::btree_map(
+            "\\+?([a-z0-9.-]{1,8}/)?[a-zA-Z0-9-]{1,8}",
+            proptest::option::of("[^\\x00]*"),
+            1..4,
+        ));
+        let source = proptest::option::of((
+            "[a-zA-Z][a-zA-Z0-9.\\[\\]{}|-]{0,15}",
+            proptest::option::of(("~?[a-z0-9]{1,9}", "[a-z0-9.:-]{1,20}")),
+        ));
+        let command = "[A-Za-z]{1,12}|[0-9]{3}";
+        let middle = proptest::collection::vec("[^\\x00\\r\\n :][^\\x00\\r\\n ]*", 0..14);
+        let last = proptest::option::of("[^\\x00\\r\\n]*");
+
+        (tags, source, command, middle, last).prop_map(|(tags, source, command, middle, last)| {
+            let tags = tags.map(|collection| {
+                let mut tags = Tags::new();
+                for (k, v) in collection {
+                    tags.insert(k, v.map_or(TagValue::True, TagValue::String));
+                }
+                tags
+            });
+
+            let source = source.map(|(nick, user_host)| match user_host {
+                Some((user, host)) => Prefix { nick, user: Some(user), host: Some(host) },
+                None => Prefix { nick, user: None, host: None },
+            });
+
+            let mut params = middle;
+            params.extend(last);
+
+            RawMsg { tags, source, command, params }
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(msg in valid_msg()) {
+            prop_assert_eq!(Ok(()), msg.validate());
+
+            let line = msg.to_string();
+            prop_assert_eq!(&msg, &line.parse::<RawMsg>().unwrap());
+            prop_assert_eq!(&line, &line.parse::<RawMsg>().unwrap().to_string());
+        }
+    }
+
     #[test]
     fn to_string_simple_test() {
         let sample = RawMsg{
@@ -206,7 +436,7 @@ mod tests {
     #[test]
     fn to_string_complete_test() {
         let sample = RawMsg{
-            tags: Some(Tags::from_string("id=234AB;rose".to_string())), 
+            tags: Some("id=234AB;rose".parse().unwrap()), 
             source: Some(Prefix{
                 nick: "dan".to_string(),
                 user: Some("d".to_string()),
@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+/*
+ * When to try connecting again after losing a server
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    /// The wait before the first attempt
+    pub initial_delay: Duration,
+    /// The most the wait can grow to
+    pub max_delay: Duration,
+    /// What each failed attempt multiplies the wait by
+    pub multiplier: u32,
+    /// How much of the wait is random, from 0 to 1, so a netsplit doesn't
+    /// have every client coming back at the same moment
+    pub jitter: f64,
+    /// Attempts in a row before giving up, or None to keep trying
+    pub max_attempts: Option<u32>,
+    /// How long a connection has to stay up for the attempts to count from
+    /// 1 again, so a server that drops us straight after registering still
+    /// runs into `max_attempts`
+    pub min_uptime: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(300),
+            multiplier: 2,
+            jitter: 0.5,
+            max_attempts: None,
+            min_uptime: Duration::from_secs(60),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+
+    /// Whether attempt number `attempt`, counting from 1, should be made
+    pub fn allows(&self, attempt: u32) -> bool {
+        self.max_attempts.map_or(true, |max| attempt <= max)
+    }
+
+    /// The wait before attempt number `attempt`, counting from 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.delay_with(attempt, random_fraction())
+    }
+
+    // `random` being from 0 to 1, the jitter taking up to that fraction of
+    // the wait off
+    fn delay_with(&self, attempt: u32, random: f64) -> Duration {
+        let factor = self.multiplier.saturating_pow(attempt.saturating_sub(1));
+        let delay = self.initial_delay.checked_mul(factor).unwrap_or(self.max_delay).min(self.max_delay);
+
+        delay.mul_f64(1.0 - self.jitter.clamp(0.0, 1.0) * random)
+    }
+}
+
+fn random_fraction() -> f64 {
+    let mut bytes = [0u8; 4];
+
+    match SystemRandom::new().fill(&mut bytes) {
+        Ok(()) => u32::from_be_bytes(bytes) as f64 / u32::MAX as f64,
+        Err(_) => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_test() {
+        let policy = ReconnectPolicy {
+            max_delay: Duration::from_secs(10),
+            ..ReconnectPolicy::default()
+        };
+
+        assert_eq!(Duration::from_secs(1), policy.delay_with(1, 0.0));
+        assert_eq!(Duration::from_secs(2), policy.delay_with(2, 0.0));
+        assert_eq!(Duration::from_secs(8), policy.delay_with(4, 0.0));
+        assert_eq!(Duration::from_secs(10), policy.delay_with(5, 0.0));
+        assert_eq!(Duration::from_secs(10), policy.delay_with(u32::MAX, 0.0));
+    }
+
+    #[test]
+    fn jitter_test() {
+        let policy = ReconnectPolicy::default();
+
+        assert_eq!(Duration::from_secs(4), policy.delay_with(3, 0.0));
+        assert_eq!(Duration::from_secs(2), policy.delay_with(3, 1.0));
+
+        for _ in 0..100 {
+            let delay = policy.delay(3);
+            assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(4));
+        }
+    }
+
+    #[test]
+    fn max_attempts_test() {
+        let policy = ReconnectPolicy {
+            max_attempts: Some(3),
+            ..ReconnectPolicy::default()
+        };
+
+        assert!(policy.allows(3));
+        assert!(!policy.allows(4));
+        assert!(ReconnectPolicy::default().allows(u32::MAX));
+    }
+}
@@ -0,0 +1,611 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::num::NonZeroU32;
+
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, hmac, pbkdf2};
+
+use crate::protocol::command::Command;
+use crate::protocol::numeric::Response;
+use crate::protocol::wire::RawMsg;
+
+/*
+ * SASL authentication over AUTHENTICATE, run between CAP ACK of sasl and
+ * CAP END. Like the cap module this does no IO of its own.
+ */
+
+// AUTHENTICATE payloads are base64 sent in chunks of at most this many bytes
+const CHUNK_LENGTH: usize = 400;
+
+/// A SASL mechanism, answering each challenge from the server in turn
+pub trait Mechanism: Send {
+    /// The name sent in `AUTHENTICATE <name>`
+    fn name(&self) -> &'static str;
+
+    /// The response to a decoded challenge, the first being empty
+    fn respond(&mut self, challenge: &[u8]) -> Result<Vec<u8>, SaslError>;
+
+    /// Whether the exchange has got far enough for a 903 to be believed,
+    /// for mechanisms where the server has to prove itself too
+    fn is_complete(&self) -> bool {
+        true
+    }
+}
+
+/// Picks the first of `mechanisms`, in order of preference, that the server
+/// lists. A server that doesn't list any gets the last, as the one most
+/// likely to be supported.
+pub fn choose(mechanisms: Vec<Box<dyn Mechanism>>, offered: &[&str]) -> Option<Box<dyn Mechanism>> {
+    if offered.is_empty() {
+        return mechanisms.into_iter().last();
+    }
+
+    mechanisms.into_iter().find(|m| offered.iter().any(|o| o.eq_ignore_ascii_case(m.name())))
+}
+
+/// SASL PLAIN, RFC 4616
+pub struct Plain {
+    account: String,
+    password: String,
+}
+
+impl Plain {
+
+    pub fn new(account: String, password: String) -> Plain {
+        Plain{account, password}
+    }
+}
+
+impl Mechanism for Plain {
+
+    fn name(&self) -> &'static str {
+        "PLAIN"
+    }
+
+    fn respond(&mut self, _challenge: &[u8]) -> Result<Vec<u8>, SaslError> {
+        // authzid NUL authcid NUL password, the authzid being the account too
+        Ok(format!("{}\0{}\0{}", self.account, self.account, self.password).into_bytes())
+    }
+}
+
+/// SASL EXTERNAL, RFC 4422, where the server identifies us by the client
+/// certificate we presented during the TLS handshake
+pub struct External {
+    authzid: Option<String>,
+}
+
+impl External {
+
+    /// `authzid` is only needed to log in as an account other than the one
+    /// the certificate is registered to
+    pub fn new(authzid: Option<String>) -> External {
+        External{authzid}
+    }
+}
+
+impl Mechanism for External {
+
+    fn name(&self) -> &'static str {
+        "EXTERNAL"
+    }
+
+    fn respond(&mut self, _challenge: &[u8]) -> Result<Vec<u8>, SaslError> {
+        // an empty response goes out as a lone +
+        Ok(self.authzid.clone().unwrap_or_default().into_bytes())
+    }
+}
+
+// bytes of randomness in our SCRAM nonce
+const NONCE_LENGTH: usize = 18;
+
+enum ScramStep {
+    ClientFirst,
+    ClientFinal,
+    Verify { server_signature: Vec<u8> },
+    Done,
+}
+
+/// SASL SCRAM-SHA-256, RFC 7677, which never sends the password and has the
+/// server prove it knows it too
+pub struct Scram {
+    account: String,
+    password: String,
+    nonce: String,
+    // client-first-message-bare, which goes into the AuthMessage
+    client_first: String,
+    step: ScramStep,
+}
+
+impl Scram {
+
+    pub fn new(account: String, password: String) -> Scram {
+        let mut nonce = [0u8; NONCE_LENGTH];
+        SystemRandom::new().fill(&mut nonce).expect("no system randomness");
+
+        Scram::with_nonce(account, password, base64::encode(nonce))
+    }
+
+    fn with_nonce(account: String, password: String, nonce: String) -> Scram {
+        Scram {
+            account,
+            password,
+            nonce,
+            client_first: String::new(),
+            step: ScramStep::ClientFirst,
+        }
+    }
+
+    fn client_first(&mut self) -> Vec<u8> {
+        // = and , are the only characters a saslname has to escape
+        let name = self.account.replace('=', "=3D").replace(',', "=2C");
+        self.client_first = format!("n={},r={}", name, self.nonce);
+
+        // n,, being no channel binding and no authzid
+        format!("n,,{}", self.client_first).into_bytes()
+    }
+
+    fn client_final(&mut self, server_first: &[u8]) -> Result<Vec<u8>, SaslError> {
+        let server_first = std::str::from_utf8(server_first)
+            .map_err(|_| SaslError::InvalidChallenge("server-first-message isn't UTF-8".to_string()))?;
+
+        let mut nonce = None;
+        let mut salt = None;
+        let mut iterations = None;
+
+        for attr in server_first.split(',') {
+            match attr.split_once('=') {
+                Some(("r", value)) => nonce = Some(value),
+                Some(("s", value)) => salt = base64::decode(value).ok(),
+                Some(("i", value)) => iterations = value.parse::<u32>().ok().and_then(NonZeroU32::new),
+                // mandatory extensions we can't know how to handle
+                Some(("m", _)) => return Err(SaslError::InvalidChallenge("unsupported SCRAM extension".to_string())),
+                _ => {}
+            }
+        }
+
+        let invalid = |attr: &str| SaslError::InvalidChallenge(format!("missing or invalid {} in server-first-message", attr));
+        let nonce = nonce.ok_or_else(|| invalid("nonce"))?;
+        let salt = salt.ok_or_else(|| invalid("salt"))?;
+        let iterations = iterations.ok_or_else(|| invalid("iteration count"))?;
+
+        // the server's nonce has to extend ours, or it isn't answering us
+        if !nonce.starts_with(&self.nonce) || nonce.len() == self.nonce.len() {
+            return Err(SaslError::InvalidChallenge("server nonce doesn't extend ours".to_string()));
+        }
+
+        // the password isn't SASLprepped, which only matters outside ASCII
+        let mut salted_password = [0u8; digest::SHA256_OUTPUT_LEN];
+        pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, &salt, self.password.as_bytes(), &mut salted_password);
+        let salted_password = hmac::Key::new(hmac::HMAC_SHA256, &salted_password);
+
+        let client_key = hmac::sign(&salted_password, b"Client Key");
+        let stored_key = digest::digest(&digest::SHA256, client_key.as_ref());
+        let server_key = hmac::sign(&salted_password, b"Server Key");
+
+        // biws being n,, base64 encoded
+        let without_proof = format!("c=biws,r={}", nonce);
+        let auth_message = format!("{},{},{}", self.client_first, server_first, without_proof);
+
+        let client_signature = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, stored_key.as_ref()), auth_message.as_bytes());
+        let proof: Vec<u8> = client_key.as_ref().iter()
+            .zip(client_signature.as_ref())
+            .map(|(k, s)| k ^ s)
+            .collect();
+
+        let server_signature = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, server_key.as_ref()), auth_message.as_bytes());
+        self.step = ScramStep::Verify { server_signature: server_signature.as_ref().to_vec() };
+
+        Ok(format!("{},p={}", without_proof, base64::encode(proof)).into_bytes())
+    }
+}
+
+impl Mechanism for Scram {
+
+    fn name(&self) -> &'static str {
+        "SCRAM-SHA-256"
+    }
+
+    fn respond(&mut self, challenge: &[u8]) -> Result<Vec<u8>, SaslError> {
+        match &self.step {
+            ScramStep::ClientFirst => {
+                self.step = ScramStep::ClientFinal;
+                Ok(self.client_first())
+            }
+            ScramStep::ClientFinal => self.client_final(challenge),
+            ScramStep::Verify { server_signature } => {
+                let server_final = String::from_utf8_lossy(challenge);
+
+                if let Some(error) = server_final.strip_prefix("e=") {
+                    return Err(SaslError::Failed(error.to_string()));
+                }
+
+                let verified = server_final.strip_prefix("v=")
+                    .and_then(|v| base64::decode(v).ok())
+                    .is_some_and(|v| ring::constant_time::verify_slices_are_equal(&v, server_signature).is_ok());
+
+                if !verified {
+                    return Err(SaslError::ServerSignature);
+                }
+
+                self.step = ScramStep::Done;
+                Ok(vec![])
+            }
+            ScramStep::Done => Err(SaslError::InvalidChallenge("unexpected challenge after server-final-message".to_string())),
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        matches!(self.step, ScramStep::Done)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SaslError {
+    /// 904, the credentials were rejected
+    Failed(String),
+    /// 905, a response was more than the server would take
+    TooLong,
+    /// 906, the exchange was aborted
+    Aborted,
+    /// 907, we're already logged in
+    AlreadyAuthenticated,
+    /// 902, the nick we're using belongs to another account
+    NickLocked,
+    /// The server's challenge couldn't be used
+    InvalidChallenge(String),
+    /// The server couldn't prove it knows our password
+    ServerSignature,
+    /// The server doesn't offer SASL, or refused the cap
+    Unavailable,
+    /// The server offers SASL, but none of these mechanisms
+    NoMechanism(String),
+}
+
+impl fmt::Display for SaslError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaslError::Failed(reason) => write!(f, "SASL authentication failed: {}", reason),
+            SaslError::TooLong => write!(f, "SASL message too long"),
+            SaslError::Aborted => write!(f, "SASL authentication aborted"),
+            SaslError::AlreadyAuthenticated => write!(f, "already authenticated"),
+            SaslError::NickLocked => write!(f, "nick is locked to another account"),
+            SaslError::InvalidChallenge(reason) => write!(f, "invalid SASL challenge: {}", reason),
+            SaslError::ServerSignature => write!(f, "server signature didn't verify"),
+            SaslError::Unavailable => write!(f, "server doesn't offer SASL"),
+            SaslError::NoMechanism(names) => write!(f, "server doesn't offer SASL {}", names),
+        }
+    }
+}
+
+impl std::error::Error for SaslError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SaslState {
+    Idle,
+    /// AUTHENTICATE has been sent, challenges are being answered
+    Authenticating,
+    /// 903, CAP END can go
+    Success,
+    Failed,
+}
+
+pub struct SaslAuthenticator {
+    mechanism: Box<dyn Mechanism>,
+    // base64 of a challenge split over several AUTHENTICATE lines
+    challenge: String,
+    account: Option<String>,
+    state: SaslState,
+}
+
+impl SaslAuthenticator {
+
+    pub fn new(mechanism: Box<dyn Mechanism>) -> SaslAuthenticator {
+        SaslAuthenticator {
+            mechanism,
+            challenge: String::new(),
+            account: None,
+            state: SaslState::Idle,
+        }
+    }
+
+    pub fn state(&self) -> SaslState {
+        self.state
+    }
+
+    pub fn mechanism(&self) -> &'static str {
+        self.mechanism.name()
+    }
+
+    /// The account 900 RPL_LOGGEDIN said we're logged in as
+    pub fn account(&self) -> Option<&str> {
+        self.account.as_deref()
+    }
+
+    pub fn start(&mut self) -> RawMsg {
+        self.state = SaslState::Authenticating;
+        authenticate(self.mechanism.name())
+    }
+
+    /// Feeds a message from the server in, giving back anything to send. An
+    /// error means registration should be abandoned, or carried on without
+    /// being logged in if the caller would rather.
+    pub fn handle(&mut self, msg: &RawMsg) -> Result<Vec<RawMsg>, SaslError> {
+        if self.state != SaslState::Authenticating {
+            return Ok(vec![]);
+        }
+
+        let command = match Command::try_from(msg.clone()) {
+            Ok(command) => command,
+            Err(_) => return Ok(vec![]),
+        };
+
+        let result = match command {
+            Command::Authenticate { data } => self.challenge(&data),
+            Command::Response { response, params } => self.response(response, &params),
+            _ => Ok(vec![]),
+        };
+
+        if result.is_err() {
+            self.state = SaslState::Failed;
+        }
+
+        result
+    }
+
+    fn challenge(&mut self, data: &str) -> Result<Vec<RawMsg>, SaslError> {
+        if data != "+" {
+            self.challenge.push_str(data);
+        }
+
+        // a full chunk means there's more to come
+        if data.len() == CHUNK_LENGTH {
+            return Ok(vec![]);
+        }
+
+        let challenge = base64::decode(&self.challenge)
+            .map_err(|e| SaslError::InvalidChallenge(e.to_string()))?;
+        self.challenge.clear();
+
+        let response = self.mechanism.respond(&challenge)?;
+
+        Ok(chunks(&base64::encode(&response)).into_iter().map(|c| authenticate(&c)).collect())
+    }
+
+    fn response(&mut self, response: Response, params: &[String]) -> Result<Vec<RawMsg>, SaslError> {
+        let reason = params.last().cloned().unwrap_or_default();
+
+        match response {
+            Response::RPL_LOGGEDIN => {
+                // <client> <nick>!<user>@<host> <account> :You are now logged in as <username>
+                self.account = params.get(2).cloned();
+                Ok(vec![])
+            }
+            Response::RPL_SASLSUCCESS => {
+                if !self.mechanism.is_complete() {
+                    return Err(SaslError::ServerSignature);
+                }
+
+                self.state = SaslState::Success;
+                Ok(vec![])
+            }
+            Response::ERR_SASLFAIL => Err(SaslError::Failed(reason)),
+            Response::ERR_SASLTOOLONG => Err(SaslError::TooLong),
+            Response::ERR_SASLABORTED => Err(SaslError::Aborted),
+            Response::ERR_SASLALREADY => Err(SaslError::AlreadyAuthenticated),
+            Response::ERR_NICKLOCKED => Err(SaslError::NickLocked),
+            _ => Ok(vec![]),
+        }
+    }
+}
+
+fn authenticate(data: &str) -> RawMsg {
+    RawMsg::new("AUTHENTICATE".to_string(), Some(vec![data.to_string()]))
+}
+
+// splits base64 into AUTHENTICATE sized pieces. An empty response, or one
+// that's an exact multiple of the chunk length, is ended with a lone +.
+fn chunks(encoded: &str) -> Vec<String> {
+    let mut chunks: Vec<String> = encoded.as_bytes()
+        .chunks(CHUNK_LENGTH)
+        .map(|c| String::from_utf8_lossy(c).into_owned())
+        .collect();
+
+    if encoded.len() % CHUNK_LENGTH == 0 {
+        chunks.push("+".to_string());
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(line: &str) -> RawMsg {
+        line.parse().unwrap()
+    }
+
+    fn plain() -> SaslAuthenticator {
+        SaslAuthenticator::new(Box::new(Plain::new("karl".to_string(), "hunter2".to_string())))
+    }
+
+    #[test]
+    fn plain_test() {
+        let mut sasl = plain();
+
+        assert_eq!("AUTHENTICATE PLAIN", sasl.start().to_string());
+
+        let replies = sasl.handle(&msg("AUTHENTICATE +")).unwrap();
+        assert_eq!(1, replies.len());
+        assert_eq!(base64::encode("karl\0karl\0hunter2"), replies[0].params[0]);
+
+        sasl.handle(&msg(":irc.example.com 900 dan dan!d@localhost karl :You are now logged in as karl")).unwrap();
+        sasl.handle(&msg(":irc.example.com 903 dan :SASL authentication successful")).unwrap();
+
+        assert_eq!(SaslState::Success, sasl.state());
+        assert_eq!(Some("karl"), sasl.account());
+    }
+
+    #[test]
+    fn failure_test() {
+        let mut sasl = plain();
+        sasl.start();
+        sasl.handle(&msg("AUTHENTICATE +")).unwrap();
+
+        assert_eq!(
+            Err(SaslError::Failed("SASL authentication failed".to_string())),
+            sasl.handle(&msg(":irc.example.com 904 dan :SASL authentication failed"))
+        );
+        assert_eq!(SaslState::Failed, sasl.state());
+    }
+
+    #[test]
+    fn external_test() {
+        let mut sasl = SaslAuthenticator::new(Box::new(External::new(None)));
+
+        assert_eq!("AUTHENTICATE EXTERNAL", sasl.start().to_string());
+
+        let replies = sasl.handle(&msg("AUTHENTICATE +")).unwrap();
+        assert_eq!(vec!["AUTHENTICATE +".to_string()], replies.iter().map(|r| r.to_string()).collect::<Vec<_>>());
+
+        let mut sasl = SaslAuthenticator::new(Box::new(External::new(Some("karl".to_string()))));
+        sasl.start();
+
+        let replies = sasl.handle(&msg("AUTHENTICATE +")).unwrap();
+        assert_eq!(base64::encode("karl"), replies[0].params[0]);
+    }
+
+    fn scram() -> SaslAuthenticator {
+        // the exchange from RFC 7677
+        let scram = Scram::with_nonce("user".to_string(), "pencil".to_string(), "rOprNGfwEbeRWgbNEkqO".to_string());
+        SaslAuthenticator::new(Box::new(scram))
+    }
+
+    fn authenticate_line(data: &str) -> RawMsg {
+        msg(&format!("AUTHENTICATE {}", base64::encode(data)))
+    }
+
+    #[test]
+    fn scram_test() {
+        let mut sasl = scram();
+
+        assert_eq!("AUTHENTICATE SCRAM-SHA-256", sasl.start().to_string());
+
+        let replies = sasl.handle(&msg("AUTHENTICATE +")).unwrap();
+        assert_eq!(base64::encode("n,,n=user,r=rOprNGfwEbeRWgbNEkqO"), replies[0].params[0]);
+
+        let replies = sasl.handle(&authenticate_line("r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096")).unwrap();
+        assert_eq!(
+            base64::encode("c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="),
+            replies[0].params[0]
+        );
+
+        // as corrected by erratum 5202, the RFC's own v= being wrong
+        let replies = sasl.handle(&authenticate_line("v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=")).unwrap();
+        assert_eq!(vec!["AUTHENTICATE +".to_string()], replies.iter().map(|r| r.to_string()).collect::<Vec<_>>());
+
+        sasl.handle(&msg(":irc.example.com 903 dan :SASL authentication successful")).unwrap();
+        assert_eq!(SaslState::Success, sasl.state());
+    }
+
+    #[test]
+    fn scram_bad_server_test() {
+        let server_first = "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+
+        // a wrong signature
+        let mut sasl = scram();
+        sasl.start();
+        sasl.handle(&msg("AUTHENTICATE +")).unwrap();
+        sasl.handle(&authenticate_line(server_first)).unwrap();
+        assert_eq!(Err(SaslError::ServerSignature), sasl.handle(&authenticate_line("v=AAAA")));
+
+        // skipping server-final-message straight to success
+        let mut sasl = scram();
+        sasl.start();
+        sasl.handle(&msg("AUTHENTICATE +")).unwrap();
+        sasl.handle(&authenticate_line(server_first)).unwrap();
+        assert_eq!(Err(SaslError::ServerSignature), sasl.handle(&msg(":irc.example.com 903 dan :SASL authentication successful")));
+        assert_eq!(SaslState::Failed, sasl.state());
+
+        // a nonce that isn't ours
+        let mut sasl = scram();
+        sasl.start();
+        sasl.handle(&msg("AUTHENTICATE +")).unwrap();
+        assert!(matches!(
+            sasl.handle(&authenticate_line("r=someoneelse,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096")),
+            Err(SaslError::InvalidChallenge(_))
+        ));
+    }
+
+    #[test]
+    fn scram_escaped_name_test() {
+        let mut scram = Scram::with_nonce("a=b,c".to_string(), "pencil".to_string(), "nonce".to_string());
+        assert_eq!(b"n,,n=a=3Db=2Cc,r=nonce".to_vec(), scram.respond(b"").unwrap());
+    }
+
+    #[test]
+    fn choose_test() {
+        let mechanisms = || -> Vec<Box<dyn Mechanism>> {
+            vec![
+                Box::new(Scram::new("karl".to_string(), "hunter2".to_string())),
+                Box::new(Plain::new("karl".to_string(), "hunter2".to_string())),
+            ]
+        };
+
+        assert_eq!(Some("SCRAM-SHA-256"), choose(mechanisms(), &["PLAIN", "SCRAM-SHA-256"]).map(|m| m.name()));
+        assert_eq!(Some("PLAIN"), choose(mechanisms(), &["PLAIN", "EXTERNAL"]).map(|m| m.name()));
+        assert_eq!(Some("PLAIN"), choose(mechanisms(), &[]).map(|m| m.name()));
+        assert!(choose(mechanisms(), &["EXTERNAL"]).is_none());
+    }
+
+    #[test]
+    fn chunks_test() {
+        assert_eq!(vec!["+"], chunks(""));
+        assert_eq!(vec!["abc"], chunks("abc"));
+
+        let exact = "a".repeat(800);
+        assert_eq!(vec!["a".repeat(400), "a".repeat(400), "+".to_string()], chunks(&exact));
+
+        let over = "a".repeat(401);
+        assert_eq!(vec!["a".repeat(400), "a".to_string()], chunks(&over));
+    }
+
+    #[test]
+    fn long_response_test() {
+        let mut sasl = SaslAuthenticator::new(Box::new(Plain::new("karl".to_string(), "p".repeat(300))));
+        sasl.start();
+
+        let replies = sasl.handle(&msg("AUTHENTICATE +")).unwrap();
+        assert_eq!(2, replies.len());
+        assert_eq!(400, replies[0].params[0].len());
+
+        let joined: String = replies.iter().map(|r| r.params[0].as_ref()).collect::<Vec<&str>>().concat();
+        assert_eq!(format!("karl\0karl\0{}", "p".repeat(300)).into_bytes(), base64::decode(joined).unwrap());
+    }
+
+    #[test]
+    fn chunked_challenge_test() {
+        struct Echo;
+
+        impl Mechanism for Echo {
+            fn name(&self) -> &'static str {
+                "ECHO"
+            }
+
+            fn respond(&mut self, challenge: &[u8]) -> Result<Vec<u8>, SaslError> {
+                Ok(challenge.to_vec())
+            }
+        }
+
+        let mut sasl = SaslAuthenticator::new(Box::new(Echo));
+        sasl.start();
+
+        // exactly 400 bytes of base64, so it's followed by a +
+        let challenge = base64::encode(vec![b'x'; 300]);
+        assert!(sasl.handle(&msg(&format!("AUTHENTICATE {}", challenge))).unwrap().is_empty());
+
+        let replies = sasl.handle(&msg("AUTHENTICATE +")).unwrap();
+        let replies: Vec<String> = replies.iter().map(|r| r.to_string()).collect();
+        assert_eq!(vec![format!("AUTHENTICATE {}", challenge), "AUTHENTICATE +".to_string()], replies);
+    }
+}
@@ -0,0 +1,704 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::casemap::{CaseMapped, CaseMapping};
+use crate::isupport::ISupport;
+use crate::mode::{self, ModeChange, ModeKind};
+use crate::protocol::command::Command;
+use crate::protocol::numeric::Response;
+use crate::protocol::prefix::PrefixRef;
+use crate::protocol::wire::RawMsg;
+
+/*
+ * The channels we're in, who's in them and what we know about each of
+ * those users, built up from what the server sends. Like the cap module
+ * this does no IO of its own.
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Topic {
+    pub text: String,
+    /// The nick, or full prefix, of whoever set it
+    pub set_by: Option<String>,
+    pub set_at: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub nick: String,
+    /// The prefix symbols, such as `@` or `+`, highest first. Without
+    /// multi-prefix this is only ever the highest.
+    pub prefixes: String,
+}
+
+impl Member {
+
+    pub fn is_op(&self) -> bool {
+        self.prefixes.contains('@')
+    }
+
+    pub fn is_halfop(&self) -> bool {
+        self.prefixes.contains('%')
+    }
+
+    pub fn is_voiced(&self) -> bool {
+        self.prefixes.contains('+')
+    }
+
+    /// The symbol shown before the nick in a nick list
+    pub fn highest(&self) -> Option<char> {
+        self.prefixes.chars().next()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub name: String,
+    pub topic: Option<Topic>,
+    /// The modes set and their param, if they have one. List modes, such as
+    /// bans, aren't kept.
+    pub modes: BTreeMap<char, Option<String>>,
+    members: HashMap<CaseMapped, Member>,
+    // a NAMES reply being built, replacing the members once it ends
+    names: Option<HashMap<CaseMapped, Member>>,
+    casemapping: CaseMapping,
+}
+
+impl Channel {
+
+    fn new(name: String, casemapping: CaseMapping) -> Channel {
+        Channel {
+            name,
+            topic: None,
+            modes: BTreeMap::new(),
+            members: HashMap::new(),
+            names: None,
+            casemapping,
+        }
+    }
+
+    pub fn member(&self, nick: &str) -> Option<&Member> {
+        self.members.get(&CaseMapped::new(nick, self.casemapping))
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = &Member> {
+        self.members.values()
+    }
+
+    pub fn has_mode(&self, mode: char) -> bool {
+        self.modes.contains_key(&mode)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct User {
+    pub nick: String,
+    pub user: Option<String>,
+    pub host: Option<String>,
+    /// From extended-join, None if they're not logged in or we don't know
+    pub account: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct State {
+    nick: String,
+    channels: HashMap<CaseMapped, Channel>,
+    // everyone sharing a channel with us, and us
+    users: HashMap<CaseMapped, User>,
+    user_modes: BTreeSet<char>,
+    // kept in step with the session's CapNegotiator
+    caps: BTreeSet<String>,
+    isupport: ISupport,
+}
+
+impl State {
+
+    pub fn new(nick: String) -> State {
+        State {
+            nick,
+            channels: HashMap::new(),
+            users: HashMap::new(),
+            user_modes: BTreeSet::new(),
+            caps: BTreeSet::new(),
+            isupport: ISupport::new(),
+        }
+    }
+
+    /// Our nick, as far as the server has told us
+    pub fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    /// Our user modes, such as `i` for invisible
+    pub fn user_modes(&self) -> &BTreeSet<char> {
+        &self.user_modes
+    }
+
+    pub fn has_user_mode(&self, mode: char) -> bool {
+        self.user_modes.contains(&mode)
+    }
+
+    /// The caps the server has ACKed, less any it's since DELeted
+    pub fn caps(&self) -> &BTreeSet<String> {
+        &self.caps
+    }
+
+    pub fn is_enabled(&self, cap: &str) -> bool {
+        self.caps.contains(cap)
+    }
+
+    pub(crate) fn set_caps<'a>(&mut self, caps: impl Iterator<Item = &'a str>) {
+        self.caps = caps.map(|c| c.to_string()).collect();
+    }
+
+    /// What the server's told us it supports
+    pub fn isupport(&self) -> &ISupport {
+        &self.isupport
+    }
+
+    pub fn channel(&self, name: &str) -> Option<&Channel> {
+        self.channels.get(&self.key(name))
+    }
+
+    pub fn channels(&self) -> impl Iterator<Item = &Channel> {
+        self.channels.values()
+    }
+
+    pub fn user(&self, nick: &str) -> Option<&User> {
+        self.users.get(&self.key(nick))
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = &User> {
+        self.users.values()
+    }
+
+    /// The length of our `nick!user@host`, for splitting messages, or the
+    /// longest it could be if we've not seen it
+    pub fn prefix_length(&self) -> usize {
+        match self.user(&self.nick) {
+            Some(User{nick, user: Some(user), host: Some(host), ..}) => nick.len() + 1 + user.len() + 1 + host.len(),
+            _ => self.isupport.prefix_length(),
+        }
+    }
+
+    pub fn is_channel(&self, target: &str) -> bool {
+        self.isupport.is_channel(target)
+    }
+
+    fn key(&self, name: &str) -> CaseMapped {
+        CaseMapped::new(name, self.isupport.casemapping())
+    }
+
+    fn is_us(&self, nick: &str) -> bool {
+        self.key(nick) == self.key(&self.nick)
+    }
+
+    // the CASEMAPPING changed, which is only likely before we've joined
+    // anything, so everything's stored by the old keys
+    fn rekey(&mut self) {
+        let casemapping = self.isupport.casemapping();
+
+        self.users = self.users.drain().map(|(_, u)| (CaseMapped::new(&u.nick, casemapping), u)).collect();
+        self.channels = self.channels.drain().map(|(_, mut channel)| {
+            channel.members = channel.members.drain().map(|(_, m)| (CaseMapped::new(&m.nick, casemapping), m)).collect();
+            channel.names = None;
+            channel.casemapping = casemapping;
+
+            (CaseMapped::new(&channel.name, casemapping), channel)
+        }).collect();
+    }
+
+    /// Feeds a message from the server in
+    pub fn handle(&mut self, msg: &RawMsg) {
+        let casemapping = self.isupport.casemapping();
+        self.isupport.handle(msg);
+
+        if casemapping != self.isupport.casemapping() {
+            self.rekey();
+        }
+
+        let source = msg.source.as_ref();
+        let nick = source.map(|s| s.nick.as_str()).unwrap_or("");
+
+        let command = match Command::try_from(msg.clone()) {
+            Ok(command) => command,
+            Err(_) => return,
+        };
+
+        match command {
+            Command::Join { channels, account, .. } => {
+                // anyone else only matters in a channel we're in, as that's
+                // what they'd be forgotten by
+                let shared = self.is_us(nick) || channels.iter().any(|c| self.channels.contains_key(&self.key(c)));
+
+                if let Some(source) = source.filter(|_| shared) {
+                    let user = self.see(nick, source.user.as_deref(), source.host.as_deref());
+                    // extended-join, * being logged out
+                    if let Some(account) = account {
+                        user.account = Some(account).filter(|a| a != "*");
+                    }
+                }
+
+                for channel in channels {
+                    self.join(nick, channel);
+                }
+            }
+            Command::Part { channels, .. } => {
+                for channel in channels {
+                    self.part(nick, &channel);
+                }
+            }
+            Command::Kick { channel, user, .. } => self.part(&user, &channel),
+            Command::Quit { .. } => self.quit(nick),
+            Command::Nick { nick: new } => self.rename(nick, new),
+            Command::Mode { target, modes } if self.is_channel(&target) => self.modes(&target, &modes),
+            Command::Mode { target, modes } if self.is_us(&target) => {
+                for change in modes.first().map(|m| mode::parse_user(m)).unwrap_or_default() {
+                    self.user_mode(change);
+                }
+            }
+            Command::Topic { channel, topic } => {
+                let topic = topic.filter(|t| !t.is_empty()).map(|text| Topic {
+                    text,
+                    set_by: Some(nick.to_string()),
+                    set_at: Some(SystemTime::now()),
+                });
+
+                if let Some(channel) = self.channels.get_mut(&self.key(&channel)) {
+                    channel.topic = topic;
+                }
+            }
+            Command::Response { response, params } => self.response(response, &params),
+            _ => {}
+        }
+    }
+
+    fn response(&mut self, response: Response, params: &[String]) {
+        match (response, params) {
+            (Response::RPL_WELCOME, [nick, ..]) => self.nick = nick.clone(),
+            // <client> <user modes>, which replace whatever we had
+            (Response::RPL_UMODEIS, [_, modes, ..]) => {
+                self.user_modes.clear();
+
+                for change in mode::parse_user(modes) {
+                    self.user_mode(change);
+                }
+            }
+            // <client> <channel> <modestring> <mode arguments>...
+            (Response::RPL_CHANNELMODEIS, [_, channel, modes @ ..]) => {
+                if let Some(channel) = self.channels.get_mut(&self.key(channel)) {
+                    channel.modes.clear();
+                }
+
+                self.modes(channel, modes);
+            }
+            (Response::RPL_NOTOPIC, [_, channel, ..]) => {
+                if let Some(channel) = self.channels.get_mut(&self.key(channel)) {
+                    channel.topic = None;
+                }
+            }
+            (Response::RPL_TOPIC, [_, channel, text]) => {
+                if let Some(channel) = self.channels.get_mut(&self.key(channel)) {
+                    channel.topic = Some(Topic{text: text.clone(), set_by: None, set_at: None});
+                }
+            }
+            // <client> <channel> <nick> <setat>
+            (Response::RPL_TOPICWHOTIME, [_, channel, set_by, set_at, ..]) => {
+                let topic = self.channels.get_mut(&self.key(channel)).and_then(|c| c.topic.as_mut());
+
+                if let Some(topic) = topic {
+                    topic.set_by = Some(set_by.clone());
+                    topic.set_at = set_at.parse().ok().map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+                }
+            }
+            (Response::RPL_NAMREPLY, [_, _, channel, names]) => self.names(channel, names),
+            (Response::RPL_ENDOFNAMES, [_, channel, ..]) => {
+                let replaced = match self.channels.get_mut(&self.key(channel)) {
+                    Some(channel) => match channel.names.take() {
+                        Some(names) => std::mem::replace(&mut channel.members, names),
+                        None => return,
+                    },
+                    None => return,
+                };
+
+                for nick in replaced.keys() {
+                    self.forget(nick);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // a user's been seen, giving back their entry to fill in
+    fn see(&mut self, nick: &str, user: Option<&str>, host: Option<&str>) -> &mut User {
+        let entry = self.users.entry(self.key(nick)).or_insert_with(|| User {
+            nick: nick.to_string(),
+            user: None,
+            host: None,
+            account: None,
+        });
+
+        if user.is_some() {
+            entry.user = user.map(|u| u.to_string());
+            entry.host = host.map(|h| h.to_string());
+        }
+
+        entry
+    }
+
+    // drops a user once they're in none of our channels
+    fn forget(&mut self, nick: &CaseMapped) {
+        let shared = self.channels.values().any(|c| c.members.contains_key(nick));
+
+        if !shared && *nick != self.key(&self.nick) {
+            self.users.remove(nick);
+        }
+    }
+
+    fn join(&mut self, nick: &str, channel: String) {
+        let name = self.key(&channel);
+
+        if self.is_us(nick) {
+            self.channels.insert(name.clone(), Channel::new(channel, self.isupport.casemapping()));
+        }
+
+        let member = Member{nick: nick.to_string(), prefixes: String::new()};
+        let nick = self.key(nick);
+
+        if let Some(channel) = self.channels.get_mut(&name) {
+            channel.members.insert(nick, member);
+        }
+    }
+
+    fn part(&mut self, nick: &str, channel: &str) {
+        if self.is_us(nick) {
+            if let Some(channel) = self.channels.remove(&self.key(channel)) {
+                for nick in channel.members.keys() {
+                    self.forget(nick);
+                }
+            }
+
+            return;
+        }
+
+        let nick = self.key(nick);
+
+        if let Some(channel) = self.channels.get_mut(&self.key(channel)) {
+            channel.members.remove(&nick);
+        }
+
+        self.forget(&nick);
+    }
+
+    fn quit(&mut self, nick: &str) {
+        let nick = self.key(nick);
+
+        for channel in self.channels.values_mut() {
+            channel.members.remove(&nick);
+        }
+
+        self.users.remove(&nick);
+    }
+
+    fn rename(&mut self, old: &str, new: String) {
+        if self.is_us(old) {
+            self.nick = new.clone();
+        }
+
+        let (old, renamed) = (self.key(old), self.key(&new));
+
+        if let Some(mut user) = self.users.remove(&old) {
+            user.nick = new.clone();
+            self.users.insert(renamed.clone(), user);
+        }
+
+        for channel in self.channels.values_mut() {
+            if let Some(mut member) = channel.members.remove(&old) {
+                member.nick = new.clone();
+                channel.members.insert(renamed.clone(), member);
+            }
+        }
+    }
+
+    // [prefix]<nick>{ [prefix]<nick>}, the nick being a full prefix with
+    // userhost-in-names
+    fn names(&mut self, channel: &str, names: &str) {
+        let name = self.key(channel);
+        if !self.channels.contains_key(&name) {
+            return;
+        }
+
+        let prefix = self.isupport.prefix();
+        let mut members = vec![];
+
+        for name in names.split(' ').filter(|n| !n.is_empty()) {
+            let split = name.find(|c| !prefix.iter().any(|(_, symbol)| *symbol == c)).unwrap_or(name.len());
+            let (prefixes, name) = name.split_at(split);
+            let source = PrefixRef::parse(name);
+
+            self.see(source.nick, source.user, source.host);
+
+            let member = Member{nick: source.nick.to_string(), prefixes: ranked(prefixes.chars(), &prefix)};
+            members.push((self.key(source.nick), member));
+        }
+
+        if let Some(channel) = self.channels.get_mut(&name) {
+            channel.names.get_or_insert_with(HashMap::new).extend(members);
+        }
+    }
+
+    fn user_mode(&mut self, change: ModeChange) {
+        if change.adding {
+            self.user_modes.insert(change.mode);
+        } else {
+            self.user_modes.remove(&change.mode);
+        }
+    }
+
+    // applies a mode string and its args, such as `+ov-b dan bob *!*@spam`
+    fn modes(&mut self, channel: &str, modes: &[String]) {
+        let prefix = self.isupport.prefix();
+        let changes = mode::parse(modes, &self.isupport.chanmodes(), &prefix);
+
+        let channel = match self.channels.get_mut(&CaseMapped::new(channel, self.isupport.casemapping())) {
+            Some(channel) => channel,
+            None => return,
+        };
+
+        for change in changes {
+            match change.kind {
+                ModeKind::Prefix => {
+                    let symbol = prefix.iter().find(|(letter, _)| *letter == change.mode).map(|(_, symbol)| *symbol);
+                    let member = change.arg.and_then(|nick| channel.members.get_mut(&CaseMapped::new(&nick, channel.casemapping)));
+
+                    if let (Some(symbol), Some(member)) = (symbol, member) {
+                        let mut prefixes: Vec<char> = member.prefixes.chars().filter(|c| *c != symbol).collect();
+                        if change.adding {
+                            prefixes.push(symbol);
+                        }
+
+                        member.prefixes = ranked(prefixes.into_iter(), &prefix);
+                    }
+                }
+                // lists aren't kept
+                ModeKind::List => {}
+                _ if change.adding => {
+                    channel.modes.insert(change.mode, change.arg);
+                }
+                _ => {
+                    channel.modes.remove(&change.mode);
+                }
+            }
+        }
+    }
+}
+
+// prefix symbols in rank order, highest first
+fn ranked(prefixes: impl Iterator<Item = char>, rank: &[(char, char)]) -> String {
+    let prefixes: Vec<char> = prefixes.collect();
+
+    rank.iter().map(|(_, symbol)| *symbol).filter(|s| prefixes.contains(s)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(line: &str) -> RawMsg {
+        line.parse().unwrap()
+    }
+
+    fn state(lines: &[&str]) -> State {
+        let mut state = State::new("dan".to_string());
+
+        for line in lines {
+            state.handle(&msg(line));
+        }
+
+        state
+    }
+
+    fn joined() -> State {
+        state(&[
+            ":irc.example.com 001 dan :Welcome",
+            ":dan!d@localhost JOIN #rust",
+            ":irc.example.com 332 dan #rust :Rust things",
+            ":irc.example.com 333 dan #rust alice!a@elsewhere 1600000000",
+            ":irc.example.com 353 dan = #rust :dan @alice +Bob",
+            ":irc.example.com 353 dan = #rust :@+carol",
+            ":irc.example.com 366 dan #rust :End of /NAMES list",
+        ])
+    }
+
+    #[test]
+    fn join_test() {
+        let state = joined();
+        let channel = state.channel("#RUST").unwrap();
+
+        assert_eq!(4, channel.members().count());
+        assert!(channel.member("alice").unwrap().is_op());
+        assert!(channel.member("bob").unwrap().is_voiced());
+        assert_eq!("@+", channel.member("carol").unwrap().prefixes);
+        assert_eq!(Some('@'), channel.member("carol").unwrap().highest());
+        assert_eq!(None, channel.member("dan").unwrap().highest());
+
+        let topic = channel.topic.as_ref().unwrap();
+        assert_eq!("Rust things", topic.text);
+        assert_eq!(Some("alice!a@elsewhere"), topic.set_by.as_deref());
+        assert_eq!(Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000)), topic.set_at);
+
+        assert_eq!(Some("d"), state.user("dan").unwrap().user.as_deref());
+        assert_eq!("Bob", state.user("BOB").unwrap().nick);
+    }
+
+    #[test]
+    fn leave_test() {
+        let mut state = joined();
+        state.handle(&msg(":dan!d@localhost JOIN #irc"));
+        state.handle(&msg(":alice!a@elsewhere JOIN #irc"));
+
+        state.handle(&msg(":bob!b@elsewhere PART #rust :bye"));
+        assert!(state.user("bob").is_none());
+
+        state.handle(&msg(":alice!a@elsewhere KICK #rust carol :spam"));
+        assert!(state.channel("#rust").unwrap().member("carol").is_none());
+
+        // alice is still in #irc with us
+        state.handle(&msg(":dan!d@localhost PART #rust"));
+        assert!(state.channel("#rust").is_none());
+        assert_eq!(Some("elsewhere"), state.user("alice").unwrap().host.as_deref());
+
+        state.handle(&msg(":alice!a@elsewhere QUIT :gone"));
+        assert!(state.user("alice").is_none());
+        assert_eq!(1, state.channel("#irc").unwrap().members().count());
+        assert!(state.user("dan").is_some());
+    }
+
+    #[test]
+    fn nick_test() {
+        let mut state = joined();
+
+        state.handle(&msg(":alice!a@elsewhere NICK alicia"));
+        assert!(state.user("alice").is_none());
+        assert!(state.channel("#rust").unwrap().member("alicia").unwrap().is_op());
+
+        state.handle(&msg(":dan!d@localhost NICK dan[away]"));
+        assert_eq!("dan[away]", state.nick());
+        // rfc1459 case mapping
+        assert!(state.user("DAN{AWAY}").is_some());
+    }
+
+    #[test]
+    fn mode_test() {
+        let mut state = joined();
+
+        state.handle(&msg(":alice!a@elsewhere MODE #rust +ov-b+kl bob carol *!*@spam secret 10"));
+        let channel = state.channel("#rust").unwrap();
+        assert_eq!("@+", channel.member("bob").unwrap().prefixes);
+        assert_eq!(Some(&Some("secret".to_string())), channel.modes.get(&'k'));
+        assert_eq!(Some(&Some("10".to_string())), channel.modes.get(&'l'));
+
+        state.handle(&msg(":alice!a@elsewhere MODE #rust -vl+nt bob"));
+        let channel = state.channel("#rust").unwrap();
+        assert_eq!("@", channel.member("bob").unwrap().prefixes);
+        assert!(!channel.has_mode('l'));
+        assert!(channel.has_mode('n') && channel.has_mode('t'));
+
+        state.handle(&msg(":irc.example.com 324 dan #rust +ns"));
+        assert_eq!(vec![&'n', &'s'], state.channel("#rust").unwrap().modes.keys().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn topic_test() {
+        let mut state = joined();
+
+        state.handle(&msg(":bob!b@elsewhere TOPIC #rust :New topic"));
+        let topic = state.channel("#rust").unwrap().topic.clone().unwrap();
+        assert_eq!("New topic", topic.text);
+        assert_eq!(Some("bob"), topic.set_by.as_deref());
+        assert!(topic.set_at.is_some());
+
+        state.handle(&msg(":bob!b@elsewhere TOPIC #rust :"));
+        assert!(state.channel("#rust").unwrap().topic.is_none());
+    }
+
+    #[test]
+    fn names_test() {
+        let mut state = joined();
+
+        // a fresh NAMES replaces the list once it's complete
+        state.handle(&msg(":irc.example.com 353 dan = #rust :dan @alice!a@elsewhere"));
+        assert_eq!(4, state.channel("#rust").unwrap().members().count());
+
+        state.handle(&msg(":irc.example.com 366 dan #rust :End of /NAMES list"));
+        assert_eq!(2, state.channel("#rust").unwrap().members().count());
+        assert!(state.user("bob").is_none());
+        assert_eq!(Some("a"), state.user("alice").unwrap().user.as_deref());
+    }
+
+    #[test]
+    fn isupport_test() {
+        let mut state = state(&[
+            ":irc.example.com 001 dan :Welcome",
+            ":irc.example.com 005 dan CASEMAPPING=ascii PREFIX=(qov)~@+ CHANMODES=b,k,jl,imnst :are supported by this server",
+            ":dan!d@localhost JOIN #rust",
+            ":irc.example.com 353 dan = #rust :~dan [alice] {Alice}",
+            ":irc.example.com 366 dan #rust :End of /NAMES list",
+        ]);
+
+        let channel = state.channel("#rust").unwrap();
+        assert_eq!(Some('~'), channel.member("dan").unwrap().highest());
+        assert_eq!(3, channel.members().count());
+        assert!(channel.member("[ALICE]").is_some());
+        assert_eq!("dan!d@localhost".len(), state.prefix_length());
+
+        state.handle(&msg(":dan!d@localhost MODE #rust +jq 3:5 {alice}"));
+        let channel = state.channel("#rust").unwrap();
+        assert_eq!(Some(&Some("3:5".to_string())), channel.modes.get(&'j'));
+        assert_eq!("~", channel.member("{alice}").unwrap().prefixes);
+    }
+
+    #[test]
+    fn user_mode_test() {
+        let mut state = joined();
+
+        state.handle(&msg(":dan MODE dan :+iwx"));
+        assert_eq!("iwx", state.user_modes().iter().collect::<String>());
+
+        state.handle(&msg(":dan!d@localhost MODE DAN -w+R"));
+        assert!(!state.has_user_mode('w') && state.has_user_mode('R'));
+
+        // someone else's, which we'd never see, or a channel's
+        state.handle(&msg(":alice!a@elsewhere MODE alice +Z"));
+        state.handle(&msg(":alice!a@elsewhere MODE #rust +Z"));
+        assert!(!state.has_user_mode('Z'));
+
+        state.handle(&msg(":irc.example.com 221 dan +Zi"));
+        assert_eq!("Zi", state.user_modes().iter().collect::<String>());
+    }
+
+    #[test]
+    fn extended_join_test() {
+        let mut state = joined();
+
+        state.handle(&msg(":eve!e@elsewhere JOIN #rust eve :Eve"));
+        assert_eq!(Some("eve"), state.user("eve").unwrap().account.as_deref());
+
+        state.handle(&msg(":mallory!m@elsewhere JOIN #rust * :Mallory"));
+        assert_eq!(None, state.user("mallory").unwrap().account);
+    }
+
+    #[test]
+    fn untracked_join_test() {
+        let mut state = joined();
+
+        // a channel we never joined, or have since left
+        state.handle(&msg(":eve!e@elsewhere JOIN #elsewhere eve :Eve"));
+        assert!(state.user("eve").is_none());
+        assert!(state.channel("#elsewhere").is_none());
+
+        state.handle(&msg(":irc.example.com 353 dan = #elsewhere :@eve"));
+        assert!(state.user("eve").is_none());
+    }
+}
@@ -0,0 +1,154 @@
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use ring::digest;
+
+/*
+ * Helper to wrap a connection in TLS, optionally presenting a client
+ * certificate for CertFP and SASL EXTERNAL. rustls is used when the
+ * tls-rustls feature is on, otherwise native-tls with tls-native.
+ */
+
+#[cfg(feature = "tls-rustls")]
+pub mod rustls;
+
+#[cfg(feature = "tls-native")]
+pub mod native;
+
+#[cfg(feature = "tls-rustls")]
+pub use self::rustls::{connect, TlsStream};
+
+#[cfg(all(feature = "tls-native", not(feature = "tls-rustls")))]
+pub use self::native::{connect, TlsStream};
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsSettings {
+    /// Extra CA certificates to trust, PEM encoded, on top of the usual roots
+    pub ca_file: Option<PathBuf>,
+    /// Trust only `ca_file`, not the usual roots
+    pub ca_only: bool,
+    /// Our client certificate chain, PEM encoded
+    pub cert_file: Option<PathBuf>,
+    /// The private key for `cert_file`, PEM encoded PKCS#8. The rustls
+    /// backend also takes RSA keys, the native-tls one doesn't.
+    pub key_file: Option<PathBuf>,
+    /// The SHA-256 fingerprint of the server's certificate in hex, colons
+    /// optional. When set that certificate is the only one accepted, even
+    /// if it's self-signed, and the roots aren't consulted. Only the rustls
+    /// backend can check one.
+    pub fingerprint: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum TlsError {
+    Io(io::Error),
+    /// A certificate file was unreadable or held no certificates
+    InvalidCertificate(PathBuf),
+    /// A key file was unreadable or held no private key
+    InvalidKey(PathBuf),
+    /// Only one of a certificate and key was given
+    MissingKeyPair,
+    /// The server name isn't one a certificate could be issued for
+    InvalidDomain(String),
+    /// The server is an IP address, which the rustls backend can't check a
+    /// certificate against
+    IpAddress(String),
+    /// The pinned fingerprint isn't 32 bytes of hex
+    InvalidFingerprint(String),
+    /// The server's certificate isn't the pinned one
+    FingerprintMismatch,
+    /// The TLS library wouldn't accept the certificate and key together
+    Config(String),
+    /// The handshake failed
+    Handshake(String),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::Io(e) => write!(f, "TLS error: {}", e),
+            TlsError::InvalidCertificate(path) => write!(f, "no certificates in {}", path.display()),
+            TlsError::InvalidKey(path) => write!(f, "no private key in {}", path.display()),
+            TlsError::MissingKeyPair => write!(f, "a client certificate needs both a cert and key file"),
+            TlsError::InvalidDomain(domain) => write!(f, "invalid server name {}", domain),
+            TlsError::IpAddress(ip) => write!(f, "can't verify a certificate for IP address {}, use a host name", ip),
+            TlsError::InvalidFingerprint(fingerprint) => write!(f, "invalid certificate fingerprint {}", fingerprint),
+            TlsError::FingerprintMismatch => write!(f, "server certificate doesn't match the pinned fingerprint"),
+            TlsError::Config(e) => write!(f, "invalid TLS config: {}", e),
+            TlsError::Handshake(e) => write!(f, "TLS handshake failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+impl From<io::Error> for TlsError {
+    fn from(e: io::Error) -> TlsError {
+        TlsError::Io(e)
+    }
+}
+
+/// Parses a hex SHA-256 fingerprint, such as `openssl x509 -fingerprint
+/// -sha256` prints
+pub fn parse_fingerprint(fingerprint: &str) -> Result<Vec<u8>, TlsError> {
+    let invalid = || TlsError::InvalidFingerprint(fingerprint.to_string());
+
+    let hex: Vec<u8> = fingerprint.bytes().filter(|b| *b != b':').collect();
+    if hex.len() != digest::SHA256_OUTPUT_LEN * 2 {
+        return Err(invalid());
+    }
+
+    hex.chunks(2)
+        .map(|pair| std::str::from_utf8(pair).ok().and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(invalid)
+}
+
+/// Whether a DER encoded certificate is the one `pinned` is the fingerprint of
+pub fn matches_fingerprint(der: &[u8], pinned: &[u8]) -> bool {
+    ring::constant_time::verify_slices_are_equal(digest::digest(&digest::SHA256, der).as_ref(), pinned).is_ok()
+}
+
+/// The host part of `host:port`, which is sent for SNI and which the
+/// certificate has to match
+pub fn server_name(server: &str) -> &str {
+    // an address without a port, which for IPv6 is full of colons
+    if server.parse::<IpAddr>().is_ok() {
+        return server;
+    }
+
+    // an IPv6 literal, [::1]:6697
+    if let Some((host, _)) = server.strip_prefix('[').and_then(|s| s.split_once(']')) {
+        return host;
+    }
+
+    server.rsplit_once(':').map_or(server, |(host, _)| host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fingerprint_test() {
+        let colons = "AB:".repeat(31) + "AB";
+        assert_eq!(vec![0xab; 32], parse_fingerprint(&colons).unwrap());
+        assert_eq!(vec![0xab; 32], parse_fingerprint(&"ab".repeat(32)).unwrap());
+
+        assert!(matches!(parse_fingerprint("abcd"), Err(TlsError::InvalidFingerprint(_))));
+        assert!(matches!(parse_fingerprint(&"zz".repeat(32)), Err(TlsError::InvalidFingerprint(_))));
+    }
+
+    #[test]
+    fn server_name_test() {
+        assert_eq!("irc.libera.chat", server_name("irc.libera.chat:6697"));
+        assert_eq!("irc.libera.chat", server_name("irc.libera.chat"));
+        assert_eq!("::1", server_name("[::1]:6697"));
+        assert_eq!("::1", server_name("[::1]"));
+        assert_eq!("::1", server_name("::1"));
+        assert_eq!("2001:db8::1", server_name("2001:db8::1"));
+        assert_eq!("127.0.0.1", server_name("127.0.0.1:6697"));
+    }
+}
@@ -0,0 +1,270 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{
+    Certificate, ClientConfig, PrivateKey, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError,
+};
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::TlsConnector;
+
+use super::{matches_fingerprint, parse_fingerprint, TlsError, TlsSettings};
+
+pub use tokio_rustls::client::TlsStream;
+
+/*
+ * The rustls backend
+ */
+
+// accepts only the certificate with the pinned fingerprint, whoever signed it
+struct PinnedVerifier {
+    fingerprint: Vec<u8>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        presented: &[Certificate],
+        _dns_name: DNSNameRef<'_>,
+        _ocsp: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        match presented.first() {
+            Some(cert) if matches_fingerprint(&cert.0, &self.fingerprint) => Ok(ServerCertVerified::assertion()),
+            _ => Err(TLSError::General(TlsError::FingerprintMismatch.to_string())),
+        }
+    }
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, TlsError> {
+    Ok(BufReader::new(File::open(path)?))
+}
+
+/// Loads a PEM certificate chain
+pub fn load_certs(path: &Path) -> Result<Vec<Certificate>, TlsError> {
+    match pemfile::certs(&mut open(path)?) {
+        Ok(certs) if !certs.is_empty() => Ok(certs),
+        _ => Err(TlsError::InvalidCertificate(path.to_path_buf())),
+    }
+}
+
+/// Loads the first PEM private key, trying PKCS#8 before RSA
+pub fn load_key(path: &Path) -> Result<PrivateKey, TlsError> {
+    let pkcs8 = pemfile::pkcs8_private_keys(&mut open(path)?).unwrap_or_default();
+    let rsa = pemfile::rsa_private_keys(&mut open(path)?).unwrap_or_default();
+
+    pkcs8.into_iter()
+        .chain(rsa)
+        .next()
+        .ok_or_else(|| TlsError::InvalidKey(path.to_path_buf()))
+}
+
+pub fn client_config(settings: &TlsSettings) -> Result<ClientConfig, TlsError> {
+    let mut config = ClientConfig::new();
+
+    if !settings.ca_only {
+        config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    }
+
+    if let Some(path) = &settings.ca_file {
+        config.root_store
+            .add_pem_file(&mut open(path)?)
+            .map_err(|_| TlsError::InvalidCertificate(path.clone()))?;
+    }
+
+    match (&settings.cert_file, &settings.key_file) {
+        (Some(cert), Some(key)) => {
+            config
+                .set_single_client_cert(load_certs(cert)?, load_key(key)?)
+                .map_err(|e| TlsError::Config(e.to_string()))?;
+        }
+        (None, None) => {}
+        _ => return Err(TlsError::MissingKeyPair),
+    }
+
+    if let Some(fingerprint) = &settings.fingerprint {
+        let fingerprint = parse_fingerprint(fingerprint)?;
+        config.dangerous().set_certificate_verifier(Arc::new(PinnedVerifier{fingerprint}));
+    }
+
+    Ok(config)
+}
+
+/// Runs the TLS handshake over `stream`, verifying the server is `domain`
+pub async fn connect<S>(stream: S, domain: &str, settings: &TlsSettings) -> Result<TlsStream<S>, TlsError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // webpki only matches certificates against DNS names
+    if domain.parse::<IpAddr>().is_ok() {
+        return Err(TlsError::IpAddress(domain.to_string()));
+    }
+
+    let config = client_config(settings)?;
+    let dns_name = DNSNameRef::try_from_ascii_str(domain)
+        .map_err(|_| TlsError::InvalidDomain(domain.to_string()))?;
+
+    let connector = TlsConnector::from(Arc::new(config));
+    connector.connect(dns_name, stream).await.map_err(|e| TlsError::Handshake(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use crate::protocol::codec::IrcCodec;
+    use crate::protocol::wire::RawMsg;
+    use crate::sasl::{External, SaslAuthenticator, SaslState};
+
+    use futures::SinkExt;
+    use ring::digest;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::stream::StreamExt;
+    use tokio_rustls::rustls::{AllowAnyAuthenticatedClient, RootCertStore, ServerConfig, Session};
+    use tokio_rustls::TlsAcceptor;
+    use tokio_util::codec::Framed;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/certs").join(name)
+    }
+
+    fn msg(line: &str) -> RawMsg {
+        line.parse().unwrap()
+    }
+
+    // a stand-in server that insists on a client cert signed by the test CA
+    fn acceptor() -> TlsAcceptor {
+        let mut roots = RootCertStore::empty();
+        roots.add_pem_file(&mut open(&fixture("ca.pem")).unwrap()).unwrap();
+
+        let mut config = ServerConfig::new(AllowAnyAuthenticatedClient::new(roots));
+        config.set_single_cert(
+            load_certs(&fixture("server.pem")).unwrap(),
+            load_key(&fixture("server.key")).unwrap(),
+        ).unwrap();
+
+        TlsAcceptor::from(Arc::new(config))
+    }
+
+    #[test]
+    fn missing_key_test() {
+        let settings = TlsSettings {
+            cert_file: Some(fixture("client.pem")),
+            ..TlsSettings::default()
+        };
+
+        assert!(matches!(client_config(&settings), Err(TlsError::MissingKeyPair)));
+    }
+
+    // a server presenting server.pem, which isn't signed by anything we trust
+    // unless ca.pem is given
+    async fn handshake(settings: TlsSettings) -> Result<(), TlsError> {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let _ = acceptor().accept(stream).await;
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let result = connect(stream, "localhost", &settings).await.map(|_| ());
+
+        server.await.unwrap();
+        result
+    }
+
+    fn pinned(fingerprint: String) -> TlsSettings {
+        TlsSettings {
+            cert_file: Some(fixture("client.pem")),
+            key_file: Some(fixture("client.key")),
+            fingerprint: Some(fingerprint),
+            ..TlsSettings::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn ip_address_test() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move { listener.accept().await.unwrap() });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let result = connect(stream, "::1", &TlsSettings::default()).await.map(|_| ());
+        assert!(matches!(result, Err(TlsError::IpAddress(ip)) if ip == "::1"));
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn pinned_fingerprint_test() {
+        let der = &load_certs(&fixture("server.pem")).unwrap()[0].0;
+        let fingerprint: Vec<String> = digest::digest(&digest::SHA256, der).as_ref().iter().map(|b| format!("{:02X}", b)).collect();
+
+        assert!(handshake(pinned(fingerprint.join(":"))).await.is_ok());
+        assert!(matches!(handshake(pinned("00".repeat(32))).await, Err(TlsError::Handshake(_))));
+
+        // without a pin or ca.pem the server isn't trusted
+        let untrusted = TlsSettings { fingerprint: None, ..pinned(String::new()) };
+        assert!(matches!(handshake(untrusted).await, Err(TlsError::Handshake(_))));
+    }
+
+    #[tokio::test]
+    async fn sasl_external_test() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let stream = acceptor().accept(stream).await.unwrap();
+
+            let (_, session) = stream.get_ref();
+            let presented = session.get_peer_certificates().unwrap();
+            assert_eq!(load_certs(&fixture("client.pem")).unwrap()[0], presented[0]);
+
+            let mut transport = Framed::new(stream, IrcCodec::new());
+
+            assert_eq!(msg("AUTHENTICATE EXTERNAL"), transport.next().await.unwrap().unwrap());
+            transport.send(msg("AUTHENTICATE +")).await.unwrap();
+
+            assert_eq!(msg("AUTHENTICATE +"), transport.next().await.unwrap().unwrap());
+            transport.send(msg(":localhost 900 * *!*@127.0.0.1 karl :You are now logged in as karl")).await.unwrap();
+            transport.send(msg(":localhost 903 * :SASL authentication successful")).await.unwrap();
+        });
+
+        let settings = TlsSettings {
+            ca_file: Some(fixture("ca.pem")),
+            cert_file: Some(fixture("client.pem")),
+            key_file: Some(fixture("client.key")),
+            ..TlsSettings::default()
+        };
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let stream = connect(stream, "localhost", &settings).await.unwrap();
+        let mut transport = Framed::new(stream, IrcCodec::new());
+
+        let mut sasl = SaslAuthenticator::new(Box::new(External::new(None)));
+        transport.send(sasl.start()).await.unwrap();
+
+        while sasl.state() == SaslState::Authenticating {
+            let msg = transport.next().await.unwrap().unwrap();
+
+            for reply in sasl.handle(&msg).unwrap() {
+                transport.send(reply).await.unwrap();
+            }
+        }
+
+        assert_eq!(SaslState::Success, sasl.state());
+        assert_eq!(Some("karl"), sasl.account());
+
+        server.await.unwrap();
+    }
+}